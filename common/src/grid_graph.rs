@@ -0,0 +1,262 @@
+use ndarray::prelude::*;
+use petgraph::visit::{
+	Data, EdgeRef, GraphBase, IntoEdgeReferences, IntoEdges, IntoNeighbors, VisitMap, Visitable,
+};
+
+pub type Node = [usize; 2];
+
+// anything that can be looked up like a two-dimensional array, which may
+// compute its values on the fly instead of storing them
+pub trait Grid {
+	type Value: Copy;
+
+	fn dim(&self) -> (usize, usize);
+
+	// None outside of the grid
+	fn value(&self, node: Node) -> Option<Self::Value>;
+}
+
+impl<T: Copy> Grid for Array2<T> {
+	type Value = T;
+
+	fn dim(&self) -> (usize, usize) {
+		ArrayBase::dim(self)
+	}
+
+	fn value(&self, node: Node) -> Option<T> {
+		self.get(node).copied()
+	}
+}
+
+pub const ORTHOGONAL: [(isize, isize); 4] = [(-1, 0), (0, -1), (0, 1), (1, 0)];
+pub const SURROUNDING: [(isize, isize); 8] = [
+	(-1, -1),
+	(-1, 0),
+	(-1, 1),
+	(0, -1),
+	(0, 1),
+	(1, -1),
+	(1, 0),
+	(1, 1),
+];
+
+// a grid as a graph for petgraph's algorithms: cells are nodes, and there is an
+// edge from every cell to each passable cell in its neighborhood; both closures
+// are type parameters, so that they can be inlined into the search
+pub struct GridGraph<G, P, F> {
+	pub grid: G,
+	pub neighborhood: Vec<(isize, isize)>,
+	pub passable: P,
+	// cost of moving from the first cell onto the second
+	pub cost: F,
+}
+
+impl<G: Grid, F> GridGraph<G, fn(G::Value) -> bool, F> {
+	// orthogonal neighbors and everything passable
+	pub fn new(grid: G, cost: F) -> Self {
+		GridGraph {
+			grid,
+			neighborhood: ORTHOGONAL.to_vec(),
+			passable: |_| true,
+			cost,
+		}
+	}
+}
+
+impl<G: Grid, P: Fn(G::Value) -> bool, F> GridGraph<G, P, F> {
+	pub fn with_passable<Q: Fn(G::Value) -> bool>(self, passable: Q) -> GridGraph<G, Q, F> {
+		GridGraph {
+			grid: self.grid,
+			neighborhood: self.neighborhood,
+			passable,
+			cost: self.cost,
+		}
+	}
+
+	pub fn neighbor(&self, node: Node, offset: (isize, isize)) -> Option<Node> {
+		let neighbor = [
+			node[0].checked_add_signed(offset.0)?,
+			node[1].checked_add_signed(offset.1)?,
+		];
+		let value = self.grid.value(neighbor)?;
+		(self.passable)(value).then_some(neighbor)
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct GridMap(Array2<bool>);
+
+impl VisitMap<Node> for GridMap {
+	fn visit(&mut self, node: Node) -> bool {
+		!std::mem::replace(&mut self.0[node], true)
+	}
+
+	fn is_visited(&self, node: &Node) -> bool {
+		self.0[*node]
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct GridEdge<C> {
+	source: Node,
+	target: Node,
+	cost: C,
+}
+
+impl<C: Copy> EdgeRef for GridEdge<C> {
+	type NodeId = Node;
+	type EdgeId = (Node, Node);
+	type Weight = C;
+
+	fn source(&self) -> Node {
+		self.source
+	}
+
+	fn target(&self) -> Node {
+		self.target
+	}
+
+	fn weight(&self) -> &C {
+		&self.cost
+	}
+
+	fn id(&self) -> (Node, Node) {
+		(self.source, self.target)
+	}
+}
+
+pub struct Neighbors<'a, G, P, F> {
+	graph: &'a GridGraph<G, P, F>,
+	node: Node,
+	offsets: std::slice::Iter<'a, (isize, isize)>,
+}
+
+impl<'a, G: Grid, P: Fn(G::Value) -> bool, F> Iterator for Neighbors<'a, G, P, F> {
+	type Item = Node;
+
+	fn next(&mut self) -> Option<Node> {
+		let (graph, node) = (self.graph, self.node);
+		self.offsets
+			.find_map(|&offset| graph.neighbor(node, offset))
+	}
+}
+
+pub struct Edges<'a, G, P, F>(Neighbors<'a, G, P, F>);
+
+impl<'a, G, P, F, C> Iterator for Edges<'a, G, P, F>
+where
+	G: Grid,
+	P: Fn(G::Value) -> bool,
+	F: Fn(G::Value, G::Value) -> C,
+{
+	type Item = GridEdge<C>;
+
+	fn next(&mut self) -> Option<GridEdge<C>> {
+		let Neighbors { graph, node, .. } = self.0;
+		let source = graph.grid.value(node)?;
+		self.0.next().map(|target| GridEdge {
+			source: node,
+			target,
+			cost: (graph.cost)(source, graph.grid.value(target).unwrap()),
+		})
+	}
+}
+
+// the edges of all passable cells, row by row
+pub struct EdgeReferences<'a, G, P, F> {
+	graph: &'a GridGraph<G, P, F>,
+	nodes: ndarray::iter::IndicesIter<Ix2>,
+	edges: Option<Edges<'a, G, P, F>>,
+}
+
+impl<'a, G, P, F, C> Iterator for EdgeReferences<'a, G, P, F>
+where
+	G: Grid,
+	P: Fn(G::Value) -> bool,
+	F: Fn(G::Value, G::Value) -> C,
+{
+	type Item = GridEdge<C>;
+
+	fn next(&mut self) -> Option<GridEdge<C>> {
+		loop {
+			if let Some(edge) = self.edges.as_mut().and_then(Iterator::next) {
+				return Some(edge);
+			}
+			let (r, c) = self.nodes.next()?;
+			if (self.graph.passable)(self.graph.grid.value([r, c]).unwrap()) {
+				self.edges = Some(Edges(self.graph.neighbors([r, c])));
+			}
+		}
+	}
+}
+
+impl<G, P, F> GraphBase for GridGraph<G, P, F> {
+	type EdgeId = (Node, Node);
+	type NodeId = Node;
+}
+
+impl<G, P, F, C> Data for GridGraph<G, P, F>
+where
+	G: Grid,
+	F: Fn(G::Value, G::Value) -> C,
+{
+	type NodeWeight = G::Value;
+	type EdgeWeight = C;
+}
+
+impl<G: Grid, P, F> Visitable for GridGraph<G, P, F> {
+	type Map = GridMap;
+
+	fn visit_map(&self) -> GridMap {
+		GridMap(Array2::from_elem(self.grid.dim(), false))
+	}
+
+	fn reset_map(&self, map: &mut GridMap) {
+		map.0.fill(false);
+	}
+}
+
+impl<'a, G: Grid, P: Fn(G::Value) -> bool, F> IntoNeighbors for &'a GridGraph<G, P, F> {
+	type Neighbors = Neighbors<'a, G, P, F>;
+
+	fn neighbors(self, node: Node) -> Self::Neighbors {
+		Neighbors {
+			graph: self,
+			node,
+			offsets: self.neighborhood.iter(),
+		}
+	}
+}
+
+impl<'a, G, P, F, C> IntoEdges for &'a GridGraph<G, P, F>
+where
+	G: Grid,
+	P: Fn(G::Value) -> bool,
+	F: Fn(G::Value, G::Value) -> C,
+	C: Copy,
+{
+	type Edges = Edges<'a, G, P, F>;
+
+	fn edges(self, node: Node) -> Self::Edges {
+		Edges(self.neighbors(node))
+	}
+}
+
+impl<'a, G, P, F, C> IntoEdgeReferences for &'a GridGraph<G, P, F>
+where
+	G: Grid,
+	P: Fn(G::Value) -> bool,
+	F: Fn(G::Value, G::Value) -> C,
+	C: Copy,
+{
+	type EdgeRef = GridEdge<C>;
+	type EdgeReferences = EdgeReferences<'a, G, P, F>;
+
+	fn edge_references(self) -> Self::EdgeReferences {
+		EdgeReferences {
+			graph: self,
+			nodes: ndarray::indices(self.grid.dim()).into_iter(),
+			edges: None,
+		}
+	}
+}
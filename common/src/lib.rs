@@ -7,6 +7,10 @@ use std::{
 	str::{from_utf8, FromStr},
 };
 
+mod grid_graph;
+
+pub use grid_graph::{Grid, GridGraph, Node, ORTHOGONAL, SURROUNDING};
+
 pub fn read_digit_field(file_name: &str) -> Result<Array2<u8>> {
 	let mut bytes = Vec::new();
 	File::open(file_name)?.read_to_end(&mut bytes)?;
@@ -33,6 +37,32 @@ pub fn read_digit_field(file_name: &str) -> Result<Array2<u8>> {
 	Ok(Array2::from_shape_vec((lines, columns.unwrap()), values)?)
 }
 
+// slices of a digit field, separated by empty lines
+pub fn read_digit_volume(file_name: &str) -> Result<Array3<u8>> {
+	let mut text = String::new();
+	File::open(file_name)?.read_to_string(&mut text)?;
+	let slices = text
+		.replace("\r\n", "\n")
+		.split("\n\n")
+		.filter(|slice| !slice.trim().is_empty())
+		.map(|slice| {
+			let lines = slice.lines().filter(|line| !line.is_empty()).collect_vec();
+			let columns = lines.first().map_or(0, |line| line.len());
+			if lines.iter().any(|line| line.len() != columns) {
+				return Err(ShapeError::from_kind(IncompatibleShape).into());
+			}
+			let values = lines
+				.iter()
+				.flat_map(|line| line.bytes())
+				.map(|b| b.checked_sub(b'0').unwrap())
+				.collect();
+			Ok(Array2::from_shape_vec((lines.len(), columns), values)?)
+		})
+		.collect::<Result<Vec<_>>>()?;
+	let views = slices.iter().map(|slice| slice.view()).collect_vec();
+	Ok(ndarray::stack(Axis(0), &views)?)
+}
+
 pub fn read_comma_separated<T>(file_name: &str) -> Result<Vec<T>>
 where
 	T: FromStr,
@@ -1,42 +1,325 @@
-use color_eyre::Result;
+use color_eyre::{eyre::eyre, Result};
 use common::read_comma_separated;
-use ndarray::prelude::*;
+use ndarray::{prelude::*, ErrorKind::IncompatibleShape, ShapeError};
 use ndarray_stats::{interpolate::Midpoint, Quantile1dExt};
+use std::{
+	fs::File,
+	io::{BufRead, BufReader},
+	ops::RangeInclusive,
+};
 
-fn calculate_fuel(initial: &[u16], target: u16) -> i32 {
-	initial
-		.iter()
-		.map(|&x| (x as i32 - target as i32).abs())
-		.sum()
+// any convex, nondecreasing function of the distance works as a fuel cost
+trait FuelCost {
+	fn cost(&self, distance: u64) -> u128;
+
+	// targets guaranteed to contain an optimum, if known in closed form
+	fn candidates(&self, _positions: &[i64]) -> Option<RangeInclusive<i64>> {
+		None
+	}
 }
 
-fn triangular_number(n: i32) -> i32 {
+struct Linear;
+
+impl FuelCost for Linear {
+	fn cost(&self, distance: u64) -> u128 {
+		distance as u128
+	}
+
+	// for the L1 distance, the solution is simply the median
+	fn candidates(&self, positions: &[i64]) -> Option<RangeInclusive<i64>> {
+		let mut positions = positions.to_vec();
+		let median = ArrayViewMut::from(&mut positions)
+			.quantile_mut(0.5f64.try_into().unwrap(), &Midpoint)
+			.ok()?;
+		Some(median..=median)
+	}
+}
+
+struct Triangular;
+
+fn triangular_number(n: u64) -> u128 {
+	let n = n as u128;
 	(n * (n + 1)) / 2
 }
 
-fn calculate_fuel_triangular(initial: &[u16], target: u16) -> i32 {
-	initial
+impl FuelCost for Triangular {
+	fn cost(&self, distance: u64) -> u128 {
+		triangular_number(distance)
+	}
+
+	// the optimum is within ±1/2 of the mean, since the derivative of the total
+	// cost is n(t - mean) plus a term bounded by n/2 from the |t - x| parts
+	fn candidates(&self, positions: &[i64]) -> Option<RangeInclusive<i64>> {
+		mean_neighborhood(positions)
+	}
+}
+
+struct Quadratic;
+
+impl FuelCost for Quadratic {
+	fn cost(&self, distance: u64) -> u128 {
+		let distance = distance as u128;
+		distance * distance
+	}
+
+	// for the squared L2 distance, the solution is the mean
+	fn candidates(&self, positions: &[i64]) -> Option<RangeInclusive<i64>> {
+		mean_neighborhood(positions)
+	}
+}
+
+// user-defined costs, which must be convex for the search to be correct
+impl<F: Fn(u64) -> u128> FuelCost for F {
+	fn cost(&self, distance: u64) -> u128 {
+		self(distance)
+	}
+}
+
+fn mean_neighborhood(positions: &[i64]) -> Option<RangeInclusive<i64>> {
+	if positions.is_empty() {
+		return None;
+	}
+	let sum: i128 = positions.iter().map(|&x| x as i128).sum();
+	let mean = sum.div_euclid(positions.len() as i128) as i64;
+	Some(mean - 1..=mean + 2)
+}
+
+fn calculate_fuel(positions: &[i64], target: i64, cost: &impl FuelCost) -> u128 {
+	positions
 		.iter()
-		.map(|&x| triangular_number((x as i32 - target as i32).abs()))
+		.map(|&x| cost.cost(x.abs_diff(target)))
 		.sum()
 }
 
+// the total cost is a sum of convex functions and therefore convex itself,
+// so a binary search on its forward difference (essentially a ternary search)
+// finds an optimum in O(n log range) instead of O(n range)
+fn optimize(positions: &[i64], cost: &impl FuelCost) -> Option<(i64, u128)> {
+	let lo = *positions.iter().min()?;
+	let hi = *positions.iter().max()?;
+	let fuel = |target| calculate_fuel(positions, target, cost);
+
+	if let Some(candidates) = cost.candidates(positions) {
+		let candidates = *candidates.start().max(&lo)..=*candidates.end().min(&hi);
+		return candidates
+			.map(|target| (target, fuel(target)))
+			.min_by_key(|&(_, fuel)| fuel);
+	}
+
+	// an optimum always lies within [lo, hi] for nondecreasing costs
+	let (mut lo, mut hi) = (lo, hi);
+	while lo < hi {
+		let mid = lo + (hi - lo) / 2;
+		if fuel(mid) <= fuel(mid + 1) {
+			hi = mid;
+		} else {
+			lo = mid + 1;
+		}
+	}
+	Some((lo, fuel(lo)))
+}
+
+// one point per line, coordinates separated by commas
+fn read_points(file_name: &str) -> Result<Array2<f64>> {
+	let file = File::open(file_name)?;
+	let file = BufReader::new(file);
+	let mut rows = 0usize;
+	let mut columns = None;
+	let mut values = Vec::new();
+	for line in file.lines() {
+		let line = line?;
+		if line.trim().is_empty() {
+			continue;
+		}
+		let before = values.len();
+		for value in line.split(',') {
+			values.push(value.trim().parse::<f64>()?);
+		}
+		let length = values.len() - before;
+		if *columns.get_or_insert(length) != length {
+			return Err(ShapeError::from_kind(IncompatibleShape).into());
+		}
+		rows += 1;
+	}
+	Ok(Array2::from_shape_vec(
+		(rows, columns.unwrap_or(0)),
+		values,
+	)?)
+}
+
+// the coordinate-wise median minimizes the separable L1 distance
+fn coordinate_wise_median(points: &Array2<f64>) -> Array1<f64> {
+	points.map_axis(Axis(0), |column| {
+		let mut column = column.to_vec();
+		column.sort_unstable_by(f64::total_cmp);
+		let mid = column.len() / 2;
+		if column.len() % 2 == 0 {
+			(column[mid - 1] + column[mid]) / 2.0
+		} else {
+			column[mid]
+		}
+	})
+}
+
+fn total_distance(points: &Array2<f64>, target: ArrayView1<f64>) -> f64 {
+	points
+		.rows()
+		.into_iter()
+		.map(|point| (&point - &target).mapv(|d| d * d).sum().sqrt())
+		.sum()
+}
+
+#[derive(Clone, Copy, Debug)]
+struct WeiszfeldOptions {
+	// stop once an update moves the estimate by less than this
+	tolerance: f64,
+	max_iterations: usize,
+}
+
+impl Default for WeiszfeldOptions {
+	fn default() -> Self {
+		Self {
+			tolerance: 1e-9,
+			max_iterations: 1000,
+		}
+	}
+}
+
+#[derive(Clone, Debug)]
+struct GeometricMedian {
+	median: Array1<f64>,
+	iterations: usize,
+	converged: bool,
+}
+
+// Weiszfeld's algorithm with the Vardi–Zhang modification, which keeps the
+// iteration from getting stuck when the estimate lands exactly on an input point
+fn geometric_median(points: &Array2<f64>, options: WeiszfeldOptions) -> GeometricMedian {
+	// the centroid is a good starting point and minimizes the squared L2 distance
+	let mut median = points.mean_axis(Axis(0)).unwrap();
+	for iteration in 1..=options.max_iterations {
+		let mut numerator = Array1::<f64>::zeros(median.raw_dim());
+		let mut denominator = 0.0;
+		let mut coincident = 0usize;
+		let mut gradient = Array1::<f64>::zeros(median.raw_dim());
+		for point in points.rows() {
+			let difference = &point - &median;
+			let distance = difference.mapv(|d| d * d).sum().sqrt();
+			if distance <= f64::EPSILON * (1.0 + median.mapv(f64::abs).sum()) {
+				coincident += 1;
+				continue;
+			}
+			numerator.scaled_add(1.0 / distance, &point);
+			denominator += 1.0 / distance;
+			gradient.scaled_add(1.0 / distance, &difference);
+		}
+
+		// every point coincides with the estimate
+		if denominator == 0.0 {
+			return GeometricMedian {
+				median,
+				iterations: iteration,
+				converged: true,
+			};
+		}
+
+		let weiszfeld = numerator / denominator;
+		let next = if coincident == 0 {
+			weiszfeld
+		} else {
+			// the estimate sits on an input point, which is optimal iff the pull of
+			// all other points doesn't exceed the number of coincident points
+			let pull = gradient.mapv(|d| d * d).sum().sqrt();
+			if pull <= coincident as f64 {
+				return GeometricMedian {
+					median,
+					iterations: iteration,
+					converged: true,
+				};
+			}
+			let ratio = coincident as f64 / pull;
+			weiszfeld * (1.0 - ratio) + &median * ratio
+		};
+
+		let step = (&next - &median).mapv(|d| d * d).sum().sqrt();
+		median = next;
+		if step <= options.tolerance * (1.0 + median.mapv(f64::abs).sum()) {
+			return GeometricMedian {
+				median,
+				iterations: iteration,
+				converged: true,
+			};
+		}
+	}
+	GeometricMedian {
+		median,
+		iterations: options.max_iterations,
+		converged: false,
+	}
+}
+
 fn main() -> Result<()> {
 	color_eyre::install()?;
-	let mut input = read_comma_separated("day-07/input")?;
+	let input = read_comma_separated::<i64>("day-07/input")?;
 
-	// for the L1 distance, the solution is simply the median
-	let median =
-		ArrayViewMut::from(&mut input).quantile_mut(0.5f64.try_into().unwrap(), &Midpoint)?;
-	println!("{}", calculate_fuel(&input, median));
-
-	// for the "triangular" distance, we just use the straightforward iterative solution
-	let lo = *input.iter().min().unwrap();
-	let hi = *input.iter().max().unwrap();
-	let triangular_best = (lo..hi)
-		.into_iter()
-		.min_by_key(|&target| calculate_fuel_triangular(&input, target))
-		.unwrap();
-	println!("{}", calculate_fuel_triangular(&input, triangular_best));
+	let mut args = std::env::args().skip(1);
+	let mut extra_cost = None;
+	let mut points_file = None;
+	while let Some(arg) = args.next() {
+		if arg == "--cost" {
+			extra_cost = Some(args.next().ok_or_else(|| eyre!("--cost requires a name"))?);
+		} else {
+			points_file = Some(arg);
+		}
+	}
+
+	let (_, fuel) = optimize(&input, &Linear).ok_or_else(|| eyre!("no crabs"))?;
+	println!("{}", fuel);
+
+	let (_, fuel) = optimize(&input, &Triangular).ok_or_else(|| eyre!("no crabs"))?;
+	println!("{}", fuel);
+
+	// other cost functions, to play around with
+	if let Some(name) = extra_cost {
+		let result = match name.as_str() {
+			"linear" => optimize(&input, &Linear),
+			"triangular" => optimize(&input, &Triangular),
+			"quadratic" => optimize(&input, &Quadratic),
+			"cubic" => optimize(&input, &|d: u64| (d as u128).pow(3)),
+			_ => return Err(eyre!("unknown cost function {}", name)),
+		};
+		let (target, fuel) = result.ok_or_else(|| eyre!("no crabs"))?;
+		println!("{} cost: {} at {}", name, fuel, target);
+	}
+
+	// optionally, crabs in n-D space, read from a file with lines like 3,4,1
+	if let Some(file_name) = points_file {
+		let points = read_points(&file_name)?;
+		if points.is_empty() {
+			return Ok(());
+		}
+
+		let coordinate_median = coordinate_wise_median(&points);
+		let l1_fuel: f64 = points
+			.rows()
+			.into_iter()
+			.map(|point| (&point - &coordinate_median).mapv(f64::abs).sum())
+			.sum();
+		println!("coordinate-wise median: {}", coordinate_median);
+		println!("L1 fuel: {}", l1_fuel);
+
+		let result = geometric_median(&points, WeiszfeldOptions::default());
+		println!(
+			"geometric median: {} ({} iterations{})",
+			result.median,
+			result.iterations,
+			if result.converged {
+				""
+			} else {
+				", not converged"
+			}
+		);
+		println!("L2 fuel: {}", total_distance(&points, result.median.view()));
+	}
 	Ok(())
 }
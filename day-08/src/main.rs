@@ -1,246 +1,410 @@
-use bitvec::{array::BitArray, bitarr, order::Lsb0};
-use color_eyre::Result;
+use bitvec::{array::BitArray, order::Lsb0};
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
 use itertools::Itertools;
+use std::{fmt, fs::read_to_string};
+use thiserror::Error;
+
+const MAX_SEGMENTS: usize = 16;
+
+type Segments = BitArray<Lsb0, u16>;
+
+static STANDARD_FONT: &str = include_str!("../fonts/standard.txt");
+
+#[derive(Error, Debug)]
+enum Error {
+	#[error("incorrect glyph format in font")]
+	IncorrectGlyphFormat,
+	#[error("font uses more than {MAX_SEGMENTS} segments")]
+	TooManySegments,
+	#[error("glyphs {0} and {1} are identical")]
+	DuplicateGlyph(char, char),
+	#[error("font can't be uniquely decoded ({0})")]
+	AmbiguousFont(String),
+	#[error("incorrect display format")]
+	IncorrectDisplayFormat,
+}
+
+use Error::{
+	AmbiguousFont, DuplicateGlyph, IncorrectDisplayFormat, IncorrectGlyphFormat, TooManySegments,
+};
 
 #[derive(Clone, Copy, Debug)]
-struct SegmentState(BitArray<Lsb0, u8>);
+struct SegmentState(Segments);
 
 impl SegmentState {
-	fn from_str(input: &str) -> Option<Self> {
+	fn from_str(input: &str, segment_count: usize) -> Option<Self> {
 		let mut state = BitArray::new(0);
 		for char in input.as_bytes().iter().copied() {
 			let index = char.checked_sub(b'a')?;
-			if index >= 7 {
+			if index as usize >= segment_count {
 				return None;
 			}
 			state.set(index as _, true);
 		}
 		Some(SegmentState(state))
 	}
+}
 
-	fn is_one(self) -> bool {
-		self.0.count_ones() == 2
-	}
-
-	fn is_four(self) -> bool {
-		self.0.count_ones() == 4
-	}
+#[derive(Clone, Debug)]
+struct Font {
+	symbols: Vec<char>,
+	glyphs: Vec<Segments>,
+	segment_count: usize,
+}
 
-	fn is_seven(self) -> bool {
-		self.0.count_ones() == 3
+impl Font {
+	// one glyph per line, symbol followed by lit segments, # starts a comment
+	fn parse(text: &str) -> Result<Self, Error> {
+		let mut symbols = Vec::new();
+		let mut glyphs = Vec::new();
+		let mut segment_count = 0;
+		for line in text.lines() {
+			let line = line.split('#').next().unwrap().trim();
+			if line.is_empty() {
+				continue;
+			}
+			let (symbol, segments) = line
+				.split_whitespace()
+				.collect_tuple()
+				.ok_or(IncorrectGlyphFormat)?;
+			let (symbol,) = symbol.chars().collect_tuple().ok_or(IncorrectGlyphFormat)?;
+			// segments are lowercase letters, of which only the first ones exist
+			if !segments.bytes().all(|b| b.is_ascii_lowercase()) {
+				return Err(IncorrectGlyphFormat);
+			}
+			let glyph = SegmentState::from_str(segments, MAX_SEGMENTS).ok_or(TooManySegments)?;
+			if let Some(index) = glyphs.iter().position(|&other| other == glyph.0) {
+				return Err(DuplicateGlyph(symbols[index], symbol));
+			}
+			segment_count = segment_count.max(glyph.0.last_one().map_or(0, |last| last + 1));
+			symbols.push(symbol);
+			glyphs.push(glyph.0);
+		}
+		Ok(Font {
+			symbols,
+			glyphs,
+			segment_count,
+		})
 	}
 
-	fn is_eight(self) -> bool {
-		self.0.count_ones() == 7
+	// decoding all glyphs of the font itself must yield each glyph again
+	// returns the number of wirings that can't be told apart
+	fn check(&self) -> Result<usize, Error> {
+		let patterns = self.glyphs.iter().copied().map(SegmentState).collect_vec();
+		let search = search(&patterns, &patterns, self);
+		if let [Some(first), Some(second), ..] = &search.decodings[..] {
+			let confusable = first
+				.iter()
+				.zip(second)
+				.filter(|(a, b)| a < b)
+				.map(|(&a, &b)| format!("{} ↔ {}", self.symbols[a], self.symbols[b]))
+				.join(", ");
+			return Err(AmbiguousFont(confusable));
+		}
+		Ok(search.wiring_count)
 	}
 }
 
-fn get_input() -> Vec<([SegmentState; 10], [SegmentState; 4])> {
-	let text = include_str!("../input");
+// observed patterns and output patterns of a single display
+type Display = (Vec<SegmentState>, Vec<SegmentState>);
+
+fn get_input(text: &str, segment_count: usize) -> Result<Vec<Display>, Error> {
+	let parse = |patterns: &str| {
+		patterns
+			.split_whitespace()
+			.map(|string| {
+				SegmentState::from_str(string, segment_count).ok_or(IncorrectDisplayFormat)
+			})
+			.collect::<Result<Vec<_>, _>>()
+	};
 	let mut ret = Vec::new();
-	for line in text.lines() {
-		let (inputs, outputs) = line.split('|').collect_tuple().unwrap();
-		let inputs: [_; 10] = array_init::from_iter(
-			inputs
-				.split_whitespace()
-				.map(|string| SegmentState::from_str(string).unwrap()),
-		)
-		.unwrap();
-		let outputs: [_; 4] = array_init::from_iter(
-			outputs
-				.split_whitespace()
-				.map(|string| SegmentState::from_str(string).unwrap()),
-		)
-		.unwrap();
-		ret.push((inputs, outputs))
+	for line in text.lines().filter(|line| !line.is_empty()) {
+		let (inputs, outputs) = line
+			.split('|')
+			.collect_tuple()
+			.ok_or(IncorrectDisplayFormat)?;
+		ret.push((parse(inputs)?, parse(outputs)?))
 	}
-	ret
+	Ok(ret)
 }
 
-fn decode(inout: ([SegmentState; 10], [SegmentState; 4])) -> usize {
-	let mut potential_encodings: [BitArray<Lsb0, u8>; 8] = [BitArray::new(0b1111111); 8];
+#[derive(Error, Debug)]
+enum DecodeError {
+	#[error("no wiring is consistent with the observed patterns")]
+	Inconsistent,
+	#[error("more than one decoding is consistent with the observed patterns")]
+	Ambiguous,
+	#[error("output pattern does not match any glyph")]
+	UnknownOutput,
+}
 
-	let one_segments = bitarr![0, 0, 1, 0, 0, 1, 0];
-	let four_segments = bitarr![0, 1, 1, 1, 0, 1, 0];
-	let seven_segments = bitarr![1, 0, 1, 0, 0, 1, 0];
+use DecodeError::{Ambiguous, Inconsistent, UnknownOutput};
 
-	let two_segments = bitarr![1, 0, 1, 1, 1, 0, 1];
-	let three_segments = bitarr![1, 0, 1, 1, 0, 1, 1];
-	let five_segments = bitarr![1, 1, 0, 1, 0, 1, 1];
+// maps each wire to the segment it is actually connected to
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Wiring(Vec<usize>);
 
-	let zero_segments = bitarr![1, 1, 1, 0, 1, 1, 1];
-	let six_segments = bitarr![1, 1, 0, 1, 1, 1, 1];
-	let nine_segments = bitarr![1, 1, 1, 1, 0, 1, 1];
+impl Wiring {
+	fn map(&self, state: SegmentState) -> Segments {
+		let mut segments = BitArray::new(0);
+		for wire in state.0.iter_ones() {
+			segments.set(self.0[wire], true);
+		}
+		segments
+	}
+}
 
-	for input in inout.0 {
-		if input.is_one() {
-			for (index, value) in input.0.iter().enumerate() {
-				potential_encodings[index] &= if value == true {
-					one_segments
-				} else {
-					!one_segments
-				};
-			}
-		} else if input.is_seven() {
-			for (index, value) in input.0.iter().enumerate() {
-				potential_encodings[index] &= if value == true {
-					seven_segments
-				} else {
-					!seven_segments
-				};
-			}
-		} else if input.is_four() {
-			for (index, value) in input.0.iter().enumerate() {
-				potential_encodings[index] &= if value == true {
-					four_segments
-				} else {
-					!four_segments
-				};
+impl fmt::Display for Wiring {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for (wire, &segment) in self.0.iter().enumerate() {
+			if wire > 0 {
+				write!(f, " ")?;
 			}
+			write!(
+				f,
+				"{}→{}",
+				(b'a' + wire as u8) as char,
+				(b'a' + segment as u8) as char
+			)?;
 		}
+		Ok(())
 	}
+}
 
-	let potential_match = |input: &SegmentState,
-	                       segments: &BitArray<Lsb0, [usize; 1]>,
-	                       potential_encodings: &[BitArray<Lsb0, u8>; 8]| {
-		input
-			.0
-			.iter_ones()
-			.all(|index| (potential_encodings[index] & *segments).any())
-	};
+// all wirings consistent with the patterns, grouped by how they decode the outputs
+struct Search<'a> {
+	outputs: &'a [SegmentState],
+	font: &'a Font,
+	wiring: Option<Wiring>,
+	wiring_count: usize,
+	decodings: Vec<Option<Vec<usize>>>,
+}
 
-	let validate_match = |input: &SegmentState,
-	                      segments: &BitArray<Lsb0, [usize; 1]>,
-	                      potential_encodings: &[BitArray<Lsb0, u8>; 8]| {
-		input
-			.0
-			.iter_ones()
-			.map(|index| potential_encodings[index] & *segments)
-			.tuple_combinations()
-			.all(|(a, b)| (a | b).count_ones() >= 2)
-	};
+impl Search<'_> {
+	fn done(&self) -> bool {
+		self.decodings.len() > 1
+	}
 
-	for input in inout.0 {
-		let mut matches = None;
-		// 2 3 5
-		if input.0.count_ones() == 5 {
-			if potential_match(&input, &two_segments, &potential_encodings)
-				&& validate_match(&input, &two_segments, &potential_encodings)
-			{
-				if matches.is_some() {
-					break;
-				}
-				matches = Some(two_segments)
-			}
-			if potential_match(&input, &three_segments, &potential_encodings)
-				&& validate_match(&input, &three_segments, &potential_encodings)
-			{
-				if matches.is_some() {
-					break;
-				}
-				matches = Some(three_segments)
-			}
-			if potential_match(&input, &five_segments, &potential_encodings)
-				&& validate_match(&input, &five_segments, &potential_encodings)
-			{
-				if matches.is_some() {
-					break;
-				}
-				matches = Some(five_segments)
-			}
+	fn found(&mut self, wiring: Wiring) {
+		let decoding = self
+			.outputs
+			.iter()
+			.map(|&output| {
+				let segments = wiring.map(output);
+				self.font.glyphs.iter().position(|&glyph| glyph == segments)
+			})
+			.collect::<Option<Vec<_>>>();
+		if !self.decodings.contains(&decoding) {
+			self.decodings.push(decoding);
 		}
-		// 0 6 9
-		else if input.0.count_ones() == 6 {
-			if potential_match(&input, &zero_segments, &potential_encodings)
-				&& validate_match(&input, &zero_segments, &potential_encodings)
-			{
-				if matches.is_some() {
-					break;
-				}
-				matches = Some(zero_segments)
-			}
-			if potential_match(&input, &six_segments, &potential_encodings)
-				&& validate_match(&input, &six_segments, &potential_encodings)
-			{
-				if matches.is_some() {
-					break;
-				}
-				matches = Some(six_segments)
-			}
-			if potential_match(&input, &nine_segments, &potential_encodings)
-				&& validate_match(&input, &nine_segments, &potential_encodings)
-			{
-				if matches.is_some() {
-					break;
-				}
-				matches = Some(nine_segments)
-			}
+		self.wiring_count += 1;
+		self.wiring.get_or_insert(wiring);
+	}
+}
+
+// once every pattern has been assigned a glyph, the remaining task is to pick
+// a distinct segment for each wire from its candidates
+fn assign_wires(
+	wire: usize,
+	candidates: &[Segments],
+	taken: Segments,
+	wiring: &mut Vec<usize>,
+	search: &mut Search,
+) {
+	if search.done() {
+		return;
+	}
+	if wire == candidates.len() {
+		search.found(Wiring(wiring.clone()));
+		return;
+	}
+	for segment in (candidates[wire] & !taken).iter_ones() {
+		let mut taken = taken;
+		taken.set(segment, true);
+		wiring.push(segment);
+		assign_wires(wire + 1, candidates, taken, wiring, search);
+		wiring.pop();
+	}
+}
+
+// assigns each pattern to a distinct glyph with the same number of segments,
+// narrowing down the candidate segments for every wire along the way
+fn assign_glyphs(
+	patterns: &[SegmentState],
+	used: &mut [bool],
+	candidates: Vec<Segments>,
+	search: &mut Search,
+) {
+	if search.done() {
+		return;
+	}
+	let (pattern, rest) = match patterns.split_first() {
+		Some(split) => split,
+		None => {
+			let mut wiring = Vec::with_capacity(candidates.len());
+			assign_wires(0, &candidates, BitArray::new(0), &mut wiring, search);
+			return;
 		}
-		if let Some(segments) = matches {
-			for (index, value) in input.0.iter().enumerate() {
-				potential_encodings[index] &= if value == true { segments } else { !segments };
-			}
+	};
+	for (index, glyph) in search.font.glyphs.iter().copied().enumerate() {
+		if used[index] || glyph.count_ones() != pattern.0.count_ones() {
+			continue;
+		}
+		// wires in the pattern must light segments of the glyph, all others must not
+		let mut narrowed = candidates.clone();
+		for (wire, candidate) in narrowed.iter_mut().enumerate() {
+			*candidate &= if pattern.0[wire] { glyph } else { !glyph };
 		}
+		if narrowed.iter().any(|candidate| candidate.not_any()) {
+			continue;
+		}
+		used[index] = true;
+		assign_glyphs(rest, used, narrowed, search);
+		used[index] = false;
+	}
+}
+
+struct Decoded {
+	// one of possibly several wirings, if some segments are indistinguishable
+	wiring: Wiring,
+	glyphs: Vec<usize>,
+}
+
+impl Decoded {
+	fn value(&self, font: &Font) -> usize {
+		// a font with n glyphs encodes base-n numbers
+		self.glyphs
+			.iter()
+			.fold(0, |value, &glyph| value * font.glyphs.len() + glyph)
 	}
 
-	// check that all patterns are unique now
-	assert!(potential_encodings
+	fn text(&self, font: &Font) -> String {
+		self.glyphs
+			.iter()
+			.map(|&glyph| font.symbols[glyph])
+			.collect()
+	}
+}
+
+fn search<'a>(
+	patterns: &[SegmentState],
+	outputs: &'a [SegmentState],
+	font: &'a Font,
+) -> Search<'a> {
+	// most constrained patterns first, e.g., those with unique segment counts
+	let patterns = patterns
 		.iter()
-		.take(7)
-		.all(|pattern| pattern.count_ones() == 1));
-
-	let encodings = potential_encodings;
-	let mut result = 0;
-	for output in inout.1 {
-		result *= 10;
-		if output.is_one() {
-			result += 1;
-		} else if output.is_four() {
-			result += 4;
-		} else if output.is_seven() {
-			result += 7;
-		} else if output.is_eight() {
-			result += 8;
-		} else {
-			let mut pattern: BitArray<Lsb0, u8> = BitArray::new(0);
-			for index in output.0.iter_ones() {
-				pattern |= encodings[index];
-			}
-			if output.0.count_ones() == 5 {
-				if pattern[..7] == two_segments[..7] {
-					result += 2;
-				} else if pattern[..7] == three_segments[..7] {
-					result += 3;
-				} else {
-					assert_eq!(pattern[..7], five_segments[..7]);
-					result += 5;
-				}
-			} else {
-				assert_eq!(output.0.count_ones(), 6);
-				if pattern[..7] == six_segments[..7] {
-					result += 6;
-				} else if pattern[..7] == nine_segments[..7] {
-					result += 9;
-				} else {
-					assert_eq!(pattern[..7], zero_segments[..7]);
-				}
-			}
-		}
+		.copied()
+		.sorted_by_key(|pattern| {
+			font.glyphs
+				.iter()
+				.filter(|glyph| glyph.count_ones() == pattern.0.count_ones())
+				.count()
+		})
+		.collect_vec();
+	let mut all_segments = BitArray::new(0);
+	all_segments[..font.segment_count].set_all(true);
+	let mut used = vec![false; font.glyphs.len()];
+	let mut search = Search {
+		outputs,
+		font,
+		wiring: None,
+		wiring_count: 0,
+		decodings: Vec::new(),
+	};
+	assign_glyphs(
+		&patterns,
+		&mut used,
+		vec![all_segments; font.segment_count],
+		&mut search,
+	);
+	search
+}
+
+// the wiring itself only needs to be unique up to segments that can't be told
+// apart, as long as every consistent wiring decodes the outputs the same way
+fn decode(
+	patterns: &[SegmentState],
+	outputs: &[SegmentState],
+	font: &Font,
+) -> Result<Decoded, DecodeError> {
+	let mut search = search(patterns, outputs, font);
+	match (search.wiring, &mut search.decodings[..]) {
+		(Some(wiring), [Some(glyphs)]) => Ok(Decoded {
+			wiring,
+			glyphs: std::mem::take(glyphs),
+		}),
+		(_, []) => Err(Inconsistent),
+		(_, [None]) => Err(UnknownOutput),
+		_ => Err(Ambiguous),
 	}
-	result
 }
 
 fn main() -> Result<()> {
 	color_eyre::install()?;
-	let input = get_input();
+
+	let mut print_wiring = false;
+	let mut font_file = None;
+	let mut input_file = None;
+	let mut args = std::env::args().skip(1);
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--wiring" => print_wiring = true,
+			"--font" => {
+				font_file = Some(args.next().ok_or_else(|| eyre!("--font requires a file"))?)
+			}
+			"--input" => {
+				input_file = Some(
+					args.next()
+						.ok_or_else(|| eyre!("--input requires a file"))?,
+				)
+			}
+			_ => return Err(eyre!("unknown argument {}", arg)),
+		}
+	}
+
+	let font = match font_file {
+		Some(file_name) => Font::parse(&read_to_string(file_name)?)?,
+		None => Font::parse(STANDARD_FONT)?,
+	};
+	let wiring_count = font.check()?;
+	if wiring_count > 1 {
+		eprintln!(
+			"note: {} wirings are indistinguishable with this font, but glyphs still decode uniquely",
+			wiring_count
+		);
+	}
+
+	let input = match input_file {
+		Some(file_name) => get_input(&read_to_string(file_name)?, font.segment_count)?,
+		None => get_input(include_str!("../input"), font.segment_count)?,
+	};
+
+	// glyphs with a unique number of segments are recognizable without decoding
 	let easily_recognizable = input
 		.iter()
-		.flat_map(|input| input.1)
+		.flat_map(|input| &input.1)
 		.filter(|display| {
-			display.is_one() || display.is_four() || display.is_seven() || display.is_eight()
+			font.glyphs
+				.iter()
+				.filter(|glyph| glyph.count_ones() == display.0.count_ones())
+				.count() == 1
 		})
 		.count();
 	println!("{}", easily_recognizable);
-	println!("{}", input.iter().copied().map(decode).sum::<usize>());
+
+	let mut sum = 0;
+	for (line, (patterns, outputs)) in input.iter().enumerate() {
+		let decoded = decode(patterns, outputs, &font)
+			.wrap_err_with(|| format!("failed to decode display {}", line + 1))?;
+		if print_wiring {
+			println!("{} {}", decoded.text(&font), decoded.wiring);
+		}
+		sum += decoded.value(&font);
+	}
+	println!("{}", sum);
 	Ok(())
 }
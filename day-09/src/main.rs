@@ -1,89 +1,339 @@
-use color_eyre::Result;
-use common::read_digit_field;
-use ndarray::prelude::*;
+use color_eyre::{eyre::eyre, Result};
+use common::{read_digit_field, read_digit_volume};
+use itertools::Itertools;
+use ndarray::{prelude::*, IntoDimension, Slice};
+use std::{
+	fs::File,
+	io::{self, BufWriter, Write},
+};
 
-fn main() -> Result<()> {
-	color_eyre::install()?;
-	let input = read_digit_field("day-09/input")?;
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Connectivity {
+	// 4 neighbors in 2-D, 6 in 3-D
+	Faces,
+	// 8 neighbors in 2-D, 26 in 3-D
+	Full,
+}
+
+fn neighbor_offsets(dimensions: usize, connectivity: Connectivity) -> Vec<Vec<isize>> {
+	(0..dimensions)
+		.map(|_| -1..=1)
+		.multi_cartesian_product()
+		.filter(|offset| {
+			let nonzero = offset.iter().filter(|&&o| o != 0).count();
+			match connectivity {
+				Connectivity::Faces => nonzero == 1,
+				Connectivity::Full => nonzero > 0,
+			}
+		})
+		.collect()
+}
+
+fn neighbor(index: &IxDyn, offset: &[isize], shape: &[usize]) -> Option<IxDyn> {
+	let mut neighbor = index.clone();
+	for ((n, &o), &s) in neighbor.slice_mut().iter_mut().zip(offset).zip(shape) {
+		*n = n.checked_add_signed(o).filter(|&n| n < s)?;
+	}
+	Some(neighbor)
+}
 
+#[derive(Clone, Debug)]
+struct Basin {
+	size: usize,
+	low_point: IxDyn,
+	// inclusive corners
+	min: IxDyn,
+	max: IxDyn,
+}
+
+fn find(parents: &mut [usize], mut label: usize) -> usize {
+	while parents[label] != label {
+		// path halving keeps the trees flat
+		parents[label] = parents[parents[label]];
+		label = parents[label];
+	}
+	label
+}
+
+// two-pass connected-component labelling using union-find, label 0 marks walls
+fn label_basins(
+	heights: &ArrayD<u8>,
+	wall: u8,
+	connectivity: Connectivity,
+) -> (ArrayD<usize>, Vec<Basin>) {
+	let mut labels = ArrayD::<usize>::zeros(heights.raw_dim());
+	let mut parents = vec![0];
+
+	// only neighbors preceding the current cell in iteration order have been labelled yet
+	let preceding = neighbor_offsets(heights.ndim(), connectivity)
+		.into_iter()
+		.filter(|offset| offset.iter().find(|&&o| o != 0) == Some(&-1))
+		.collect_vec();
+
+	// first pass: provisional labels from the preceding neighbors
+	for (index, &height) in heights.indexed_iter() {
+		if height >= wall {
+			continue;
+		}
+		let mut label = 0;
+		for offset in &preceding {
+			let other = match neighbor(&index, offset, heights.shape()) {
+				Some(other) if labels[&other] != 0 => find(&mut parents, labels[&other]),
+				_ => continue,
+			};
+			if label == 0 {
+				label = other;
+			} else if label != other {
+				let (root, child) = (label.min(other), label.max(other));
+				parents[child] = root;
+				label = root;
+			}
+		}
+		if label == 0 {
+			parents.push(parents.len());
+			label = parents.len() - 1;
+		}
+		labels[&index] = label;
+	}
+
+	// second pass: resolve equivalences to consecutive labels and collect statistics
+	let mut compact = vec![0; parents.len()];
+	let mut basins = Vec::<Basin>::new();
+	for (index, label) in labels.indexed_iter_mut() {
+		if *label == 0 {
+			continue;
+		}
+		let root = find(&mut parents, *label);
+		if compact[root] == 0 {
+			basins.push(Basin {
+				size: 0,
+				low_point: index.clone(),
+				min: index.clone(),
+				max: index.clone(),
+			});
+			compact[root] = basins.len();
+		}
+		*label = compact[root];
+		let basin = &mut basins[*label - 1];
+		basin.size += 1;
+		if heights[&index] < heights[&basin.low_point] {
+			basin.low_point = index.clone();
+		}
+		for (axis, &i) in index.slice().iter().enumerate() {
+			basin.min[axis] = basin.min[axis].min(i);
+			basin.max[axis] = basin.max[axis].max(i);
+		}
+	}
+
+	(labels, basins)
+}
+
+fn find_low_points(input: &ArrayD<u8>, wall: u8, connectivity: Connectivity) -> Vec<IxDyn> {
 	// pad ndarray to ensure the size of the window iterator matches the si
-	let mut padded_input = Array2::from_elem(Ix2(input.shape()[0] + 2, input.shape()[1] + 2), 9);
+	let padded_shape = input.shape().iter().map(|&s| s + 2).collect_vec();
+	let mut padded_input = ArrayD::from_elem(padded_shape, u8::MAX);
 	padded_input
-		.slice_mut(s![
-			1..padded_input.shape()[0] - 1,
-			1..padded_input.shape()[1] - 1
-		])
-		.assign(&input);
+		.slice_each_axis_mut(|axis| Slice::from(1..axis.len as isize - 1))
+		.assign(input);
 	// use ndarray windows to iterate and find low spots
-	let risk: usize = padded_input
-		.windows((3, 3))
+	let center = IxDyn(&vec![1; input.ndim()]);
+	let offsets = neighbor_offsets(input.ndim(), connectivity)
 		.into_iter()
-		.map(|window| {
-			if window[[1, 1]] < window[[0, 1]]
-				&& window[[1, 1]] < window[[2, 1]]
-				&& window[[1, 1]] < window[[1, 0]]
-				&& window[[1, 1]] < window[[1, 2]]
-			{
-				window[[1, 1]] as usize + 1
-			} else {
-				0
-			}
+		.map(|offset| {
+			offset
+				.iter()
+				.map(|&o| (1 + o) as usize)
+				.collect_vec()
+				.into_dimension()
+		})
+		.collect_vec();
+	padded_input
+		.windows(IxDyn(&vec![3; input.ndim()]))
+		.into_iter()
+		.zip(input.indexed_iter())
+		.filter(|(window, (_, &height))| {
+			height < wall
+				&& offsets
+					.iter()
+					.all(|offset| window[&center] < window[offset])
 		})
+		.map(|(_, (index, _))| index)
+		.collect()
+}
+
+fn hsv_to_rgb(hue: f32, saturation: f32, value: f32) -> [u8; 3] {
+	let sector = hue.rem_euclid(1.0) * 6.0;
+	let chroma = value * saturation;
+	let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+	let (r, g, b) = match sector as u8 {
+		0 => (chroma, x, 0.0),
+		1 => (x, chroma, 0.0),
+		2 => (0.0, chroma, x),
+		3 => (0.0, x, chroma),
+		4 => (x, 0.0, chroma),
+		_ => (chroma, 0.0, x),
+	};
+	let m = value - chroma;
+	[r, g, b].map(|channel| ((channel + m) * 255.0).round() as u8)
+}
+
+// one hue per basin (golden ratio steps keep neighboring labels apart),
+// darker towards the bottom, gray walls and white low points
+fn colorize(
+	heights: &Array2<u8>,
+	labels: &Array2<usize>,
+	low_points: &[IxDyn],
+	wall: u8,
+) -> Array3<u8> {
+	let (rows, columns) = heights.dim();
+	let mut image = Array3::zeros((rows, columns, 3));
+	for ((r, c), &label) in labels.indexed_iter() {
+		let color = if label == 0 {
+			[96; 3]
+		} else {
+			let hue = label as f32 * 0.618_034;
+			let value = 0.35 + 0.65 * heights[[r, c]] as f32 / wall.saturating_sub(1).max(1) as f32;
+			hsv_to_rgb(hue, 0.75, value)
+		};
+		image.slice_mut(s![r, c, ..]).assign(&aview1(&color));
+	}
+	for point in low_points {
+		image.slice_mut(s![point[0], point[1], ..]).fill(255);
+	}
+	image
+}
+
+// grayscale heights, binary PGM
+fn write_pgm(file_name: &str, heights: &Array2<u8>, low_points: &[IxDyn], wall: u8) -> Result<()> {
+	let mut image =
+		heights.mapv(|height| (height.min(wall) as usize * 200 / wall.max(1) as usize) as u8);
+	for point in low_points {
+		image[[point[0], point[1]]] = 255;
+	}
+	let mut file = BufWriter::new(File::create(file_name)?);
+	write!(file, "P5\n{} {}\n255\n", image.ncols(), image.nrows())?;
+	file.write_all(image.as_standard_layout().as_slice().unwrap())?;
+	Ok(())
+}
+
+// basin colors, binary PPM
+fn write_ppm(file_name: &str, image: &Array3<u8>) -> Result<()> {
+	let mut file = BufWriter::new(File::create(file_name)?);
+	write!(file, "P6\n{} {}\n255\n", image.shape()[1], image.shape()[0])?;
+	file.write_all(image.as_standard_layout().as_slice().unwrap())?;
+	Ok(())
+}
+
+// heights as digits on 24-bit colored backgrounds
+fn print_ansi(heights: &Array2<u8>, image: &Array3<u8>) -> Result<()> {
+	let stdout = io::stdout();
+	let mut stdout = BufWriter::new(stdout.lock());
+	for (heights, colors) in heights.rows().into_iter().zip(image.outer_iter()) {
+		for (height, color) in heights.iter().zip(colors.rows()) {
+			write!(
+				stdout,
+				"\x1b[38;2;0;0;0;48;2;{};{};{}m{}",
+				color[0], color[1], color[2], height
+			)?;
+		}
+		writeln!(stdout, "\x1b[0m")?;
+	}
+	Ok(())
+}
+
+fn main() -> Result<()> {
+	color_eyre::install()?;
+	let mut print_basins = false;
+	let mut print_colored = false;
+	let mut export_file = None;
+	let mut volume_file = None;
+	let mut connectivity = Connectivity::Faces;
+	let mut wall = 9;
+	let mut args = std::env::args().skip(1);
+	while let Some(arg) = args.next() {
+		let mut value = || args.next().ok_or_else(|| eyre!("{} requires a value", arg));
+		match arg.as_str() {
+			"--basins" => print_basins = true,
+			"--ansi" => print_colored = true,
+			"--export" => export_file = Some(value()?),
+			"--volume" => volume_file = Some(value()?),
+			"--connectivity" => {
+				connectivity = match value()?.as_str() {
+					"4" | "6" | "faces" => Connectivity::Faces,
+					"8" | "26" | "full" => Connectivity::Full,
+					other => return Err(eyre!("unknown connectivity {}", other)),
+				}
+			}
+			"--wall" => wall = value()?.parse()?,
+			_ => return Err(eyre!("unknown argument {}", arg)),
+		}
+	}
+	let input = match volume_file {
+		Some(file_name) => read_digit_volume(&file_name)?.into_dyn(),
+		None => read_digit_field("day-09/input")?.into_dyn(),
+	};
+
+	let low_points = find_low_points(&input, wall, connectivity);
+	let risk: usize = low_points
+		.iter()
+		.map(|point| input[point] as usize + 1)
 		.sum();
 	println!("{}", risk);
 
-	// same thing but standard iteration (no padding necessary)
-	let mut markers = Array2::<u8>::zeros(input.raw_dim());
-	let mut queue = Vec::new();
-	let mut basin_sizes = Vec::new();
-	let shape = input.shape();
-	for idx in ndarray::indices(shape) {
-		let r = idx[0];
-		let c = idx[1];
-		let cur = input[[r, c]];
-
-		// inverted low spot condition for early-out
-		if (r > 0 && input[[r - 1, c]] <= cur)
-			|| (r < shape[0] - 1 && input[[r + 1, c]] <= cur)
-			|| (c > 0 && input[[r, c - 1]] <= cur)
-			|| (c < shape[1] - 1 && input[[r, c + 1]] <= cur)
-		{
-			continue;
+	// every non-wall cell belongs to exactly one basin, so there is no need to
+	// flood fill from each low point separately
+	let (labels, mut basins) = label_basins(&input, wall, connectivity);
+	if print_basins {
+		for (label, basin) in basins.iter().enumerate() {
+			println!(
+				"basin {}: size {}, low point {:?}, bounds {:?}..={:?}",
+				label + 1,
+				basin.size,
+				basin.low_point.slice(),
+				basin.min.slice(),
+				basin.max.slice()
+			);
 		}
+	}
 
-		// adapted flood-fill algorithm
-		// this version uses a queue, often implemented recursively instead
-		queue.push([r, c]);
-		markers.fill(0);
-		while let Some([r, c]) = queue.pop() {
-			let cur = input[[r, c]];
-			markers[[r, c]] = 1;
-			if r > 0 && (cur + 1..9).contains(&input[[r - 1, c]]) {
-				queue.push([r - 1, c]);
-			}
-			if r < shape[0] - 1 && (cur + 1..9).contains(&input[[r + 1, c]]) {
-				queue.push([r + 1, c]);
-			}
-			if c > 0 && (cur + 1..9).contains(&input[[r, c - 1]]) {
-				queue.push([r, c - 1]);
-			}
-			if c < shape[1] - 1 && (cur + 1..9).contains(&input[[r, c + 1]]) {
-				queue.push([r, c + 1]);
+	// visually verify the basin boundaries
+	if export_file.is_some() || print_colored {
+		let heights = input
+			.view()
+			.into_dimensionality::<Ix2>()
+			.map_err(|_| eyre!("only 2-D heightmaps can be rendered"))?
+			.to_owned();
+		let labels = labels.into_dimensionality::<Ix2>()?;
+		if let Some(file_name) = export_file {
+			if file_name.ends_with(".pgm") {
+				write_pgm(&file_name, &heights, &low_points, wall)?;
+			} else if file_name.ends_with(".ppm") {
+				write_ppm(&file_name, &colorize(&heights, &labels, &low_points, wall))?;
+			} else {
+				return Err(eyre!("unsupported export format {}", file_name));
 			}
 		}
-
-		// compute and store size of basin
-		basin_sizes.push(markers.mapv(|v| v as usize).sum());
+		if print_colored {
+			print_ansi(&heights, &colorize(&heights, &labels, &low_points, wall))?;
+		}
 	}
 
-	// sort and print product of last three elements
-	basin_sizes.sort_unstable();
-	println!(
-		"{}",
-		basin_sizes[basin_sizes.len() - 3..]
-			.iter()
-			.product::<usize>()
-	);
+	// sort and print product of the three largest
+	if basins.len() < 3 {
+		eprintln!(
+			"note: fewer than three basins ({}), no product to print",
+			basins.len()
+		);
+	} else {
+		basins.sort_unstable_by_key(|basin| basin.size);
+		println!(
+			"{}",
+			basins[basins.len() - 3..]
+				.iter()
+				.map(|basin| basin.size)
+				.product::<usize>()
+		);
+	}
 
 	Ok(())
 }
@@ -1,191 +1,623 @@
-use chumsky::prelude::*;
-use std::time::Instant;
+use chumsky::{prelude::*, Stream};
+use color_eyre::{eyre::eyre, Result};
+use itertools::Itertools;
+use std::{
+	cmp::Reverse,
+	fs::{read_to_string, File},
+	io::{self, Read},
+	time::Instant,
+};
+use thiserror::Error;
 
 static INPUT: &str = include_str!("../input");
+static NAVIGATION_DELIMITERS: &str = include_str!("../delimiters/navigation.txt");
 
-fn parser() -> impl Parser<char, (), Error = Simple<char>> {
+#[derive(Error, Debug)]
+enum Error {
+	#[error("incorrect delimiter table format")]
+	IncorrectTableFormat,
+	#[error("delimiters must not be empty")]
+	EmptyDelimiter,
+	#[error("median fell outside the retained window, increase the capacity")]
+	MedianOutsideWindow,
+	#[error("no incomplete lines")]
+	NoIncompleteLines,
+}
+
+use Error::{EmptyDelimiter, IncorrectTableFormat, MedianOutsideWindow, NoIncompleteLines};
+
+#[derive(Clone, Debug)]
+struct Pair {
+	open: String,
+	close: String,
+	corruption_score: usize,
+	completion_score: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Token {
+	Open(usize),
+	Close(usize),
+}
+
+use Token::{Close, Open};
+
+// what the parser sees, None for anything that isn't a delimiter
+type Lexeme = Option<Token>;
+
+#[derive(Clone, Debug)]
+struct Delimiters {
+	pairs: Vec<Pair>,
+	multiplier: usize,
+	// candidate tokens by first byte, longest first
+	tokens: Vec<Vec<Token>>,
+}
+
+impl Delimiters {
+	// one pair per line (opener, closer and both scores) plus the completion
+	// score multiplier, # starts a comment
+	fn parse(text: &str) -> Result<Self, Error> {
+		let mut pairs = Vec::new();
+		let mut multiplier = 5;
+		for line in text.lines() {
+			let line = line.split(" #").next().unwrap().trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			match line.split_whitespace().collect_vec()[..] {
+				["multiplier", value] => {
+					multiplier = value.parse().map_err(|_| IncorrectTableFormat)?;
+				}
+				[open, close, corruption_score, completion_score] => pairs.push(Pair {
+					open: open.to_owned(),
+					close: close.to_owned(),
+					corruption_score: corruption_score.parse().map_err(|_| IncorrectTableFormat)?,
+					completion_score: completion_score.parse().map_err(|_| IncorrectTableFormat)?,
+				}),
+				_ => return Err(IncorrectTableFormat),
+			}
+		}
+		if pairs.is_empty() {
+			return Err(IncorrectTableFormat);
+		}
+		let mut tokens = vec![Vec::new(); 256];
+		for (index, pair) in pairs.iter().enumerate() {
+			for (token, text) in [(Open(index), &pair.open), (Close(index), &pair.close)] {
+				let first = *text.as_bytes().first().ok_or(EmptyDelimiter)?;
+				tokens[first as usize].push((token, text.len()));
+			}
+		}
+		let tokens = tokens
+			.into_iter()
+			.map(|mut candidates| {
+				candidates.sort_by_key(|&(_, length)| Reverse(length));
+				candidates.into_iter().map(|(token, _)| token).collect()
+			})
+			.collect();
+		Ok(Delimiters {
+			pairs,
+			multiplier,
+			tokens,
+		})
+	}
+
+	fn text(&self, token: Token) -> &str {
+		match token {
+			Open(index) => &self.pairs[index].open,
+			Close(index) => &self.pairs[index].close,
+		}
+	}
+
+	fn token_at(&self, text: &[u8]) -> Option<Token> {
+		self.tokens[*text.first()? as usize]
+			.iter()
+			.copied()
+			.find(|&token| text.starts_with(self.text(token).as_bytes()))
+	}
+
+	fn closer_at(&self, text: &[u8]) -> Option<usize> {
+		match self.token_at(text)? {
+			Close(index) => Some(index),
+			Open(_) => None,
+		}
+	}
+
+	fn max_token_length(&self) -> usize {
+		self.pairs
+			.iter()
+			.map(|pair| pair.open.len().max(pair.close.len()))
+			.max()
+			.unwrap_or(1)
+	}
+
+	fn completion_score(&self, closers: impl Iterator<Item = usize>) -> usize {
+		closers.fold(0, |old, new| {
+			old * self.multiplier + self.pairs[new].completion_score
+		})
+	}
+}
+
+// a missing closer at the end of the input is recovered by emitting it, so a
+// single parse yields the completion string, while corrupted lines still fail
+fn parser_with_recovery(
+	delimiters: &Delimiters,
+) -> impl Parser<Lexeme, String, Error = Simple<Lexeme>> + '_ {
 	recursive(|r| {
-		let parentheses = r.clone().repeated().ignored().delimited_by('(', ')');
-		let brackets = r.clone().repeated().ignored().delimited_by('[', ']');
-		let braces = r.clone().repeated().ignored().delimited_by('{', '}');
-		let angle_brackets = r.repeated().ignored().delimited_by('<', '>');
-		parentheses.or(brackets).or(braces).or(angle_brackets)
+		// any other content is ignored
+		let other = just(None).to(String::new()).boxed();
+		delimiters
+			.pairs
+			.iter()
+			.enumerate()
+			.map(|(index, pair)| {
+				let missing = pair.close.clone();
+				just(Some(Open(index)))
+					.ignore_then(r.clone().repeated())
+					.then(
+						just(Some(Close(index)))
+							.to(String::new())
+							.or(end().to(missing)),
+					)
+					// only the last nested group can be incomplete
+					.map(|(nested, missing)| nested.concat() + missing.as_str())
+					.boxed()
+			})
+			.fold(None, |alternatives: Option<BoxedParser<_, _, _>>, pair| {
+				Some(match alternatives {
+					Some(alternatives) => alternatives.or(pair).boxed(),
+					None => pair,
+				})
+			})
+			.unwrap()
+			.or(other)
 	})
 	.repeated()
-	.ignored()
+	.map(|completions| completions.concat())
 	.then_ignore(end())
 }
 
-// fn parser_with_recovery() -> impl Parser<char, (), Error = Simple<char>> {
-// 	recursive(|r| {
-// 		let parentheses = r.clone().repeated().ignored().delimited_by('(', ')');
-// 		let brackets = r.clone().repeated().ignored().delimited_by('[', ']');
-// 		let braces = r.clone().repeated().ignored().delimited_by('{', '}');
-// 		let angle_brackets = r.clone().repeated().ignored().delimited_by('<', '>');
-// 		parentheses
-// 			.or(brackets)
-// 			.or(braces)
-// 			.or(angle_brackets)
-// 	})
-// 	.repeated()
-// 	.ignored()
-// 	.then_ignore(
-// 		end()
-// 			.recover_with(nested_delimiters(
-// 				'(',
-// 				')',
-// 				[('[', ']'), ('{', '}'), ('<', '>')],
-// 				|_| (),
-// 			))
-// 			.recover_with(nested_delimiters(
-// 				'[',
-// 				']',
-// 				[('(', ')'), ('{', '}'), ('<', '>')],
-// 				|_| (),
-// 			))
-// 			.recover_with(nested_delimiters(
-// 				'{',
-// 				'}',
-// 				[('(', ')'), ('[', ']'), ('<', '>')],
-// 				|_| (),
-// 			))
-// 			.recover_with(nested_delimiters(
-// 				'<',
-// 				'>',
-// 				[('(', ')'), ('[', ']'), ('{', '}')],
-// 				|_| (),
-// 			)),
-// 	)
-// }
-
-fn main() {
-	let start = Instant::now();
+// splits a line exactly like the byte-stack checker does, so that a stray
+// character of a longer delimiter is other content for both, with the spans in
+// characters for the diagnostics
+fn lex(line: &str, delimiters: &Delimiters) -> Vec<(Lexeme, std::ops::Range<usize>)> {
+	let mut lexemes = Vec::new();
+	let (mut offset, mut column) = (0, 0);
+	while let Some(other) = line[offset..].chars().next() {
+		let token = delimiters.token_at(&line.as_bytes()[offset..]);
+		let text = token.map_or(&line[offset..offset + other.len_utf8()], |token| {
+			delimiters.text(token)
+		});
+		let width = text.chars().count();
+		lexemes.push((token, column..column + width));
+		offset += text.len();
+		column += width;
+	}
+	lexemes
+}
+
+fn parse_line(
+	line: &str,
+	parser: &impl Parser<Lexeme, String, Error = Simple<Lexeme>>,
+	delimiters: &Delimiters,
+) -> (Option<String>, Vec<Simple<Lexeme>>) {
+	let end = line.chars().count();
+	parser.parse_recovery(Stream::from_iter(
+		end..end + 1,
+		lex(line, delimiters).into_iter(),
+	))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Corruption {
+	// a closer for another pair than the innermost open one
+	Mismatched(usize),
+	// a closer with nothing to close
+	Unopened,
+}
+
+use Corruption::{Mismatched, Unopened};
+
+// the explicit stack of a single line, fed one token at a time, shared by the
+// in-memory and the streaming checker so that their scores can't drift apart
+#[derive(Clone, Debug, Default)]
+struct LineChecker {
+	// open pairs with the byte offsets they were opened at
+	stack: Vec<(usize, usize)>,
+	// bytes of the line consumed so far
+	offset: usize,
+	corruption: Option<Corruption>,
+}
+
+impl LineChecker {
+	fn reset(&mut self) {
+		self.stack.clear();
+		self.offset = 0;
+		self.corruption = None;
+	}
+
+	fn is_corrupted(&self) -> bool {
+		self.corruption.is_some()
+	}
+
+	// consumes the token or other byte at the start of the text, returns its length
+	fn feed(&mut self, text: &[u8], delimiters: &Delimiters) -> usize {
+		let length = match delimiters.token_at(text) {
+			Some(token @ Open(o)) => {
+				self.stack.push((o, self.offset));
+				delimiters.text(token).len()
+			}
+			Some(token @ Close(c)) => {
+				self.corruption = match self.stack.pop() {
+					Some((o, _)) if o == c => None,
+					Some(_) => Some(Mismatched(c)),
+					None => Some(Unopened),
+				};
+				delimiters.text(token).len()
+			}
+			None => 1,
+		};
+		self.offset += length;
+		length
+	}
+
+	// a whole line, up to the first corrupted closer
+	fn check(&mut self, line: &[u8], delimiters: &Delimiters) {
+		self.reset();
+		while self.offset < line.len() && !self.is_corrupted() {
+			self.feed(&line[self.offset..], delimiters);
+		}
+	}
+
+	fn error_score(&self, delimiters: &Delimiters) -> usize {
+		match self.corruption {
+			Some(Mismatched(closer)) => delimiters.pairs[closer].corruption_score,
+			Some(Unopened) | None => 0,
+		}
+	}
 
-	let syntax_error_score = INPUT
-		.lines()
-		.map(|line| {
-			if let Err(simple) = parser().parse(line) {
-				assert!(simple.len() == 1);
-				let simple = simple.first().unwrap();
-				if simple.reason() == &chumsky::error::SimpleReason::Unexpected {
-					match simple.found() {
-						None => 0,
-						Some(')') => 3,
-						Some(']') => 57,
-						Some('}') => 1197,
-						Some('>') => 25137,
-						_ => unreachable!(),
-					}
-				} else {
-					unreachable!();
+	// only incomplete lines have one, complete lines don't need recovery
+	fn completion_score(&self, delimiters: &Delimiters) -> Option<usize> {
+		if self.is_corrupted() || self.stack.is_empty() {
+			return None;
+		}
+		Some(delimiters.completion_score(self.stack.iter().rev().map(|&(pair, _)| pair)))
+	}
+}
+
+// delimiters still open at the end of the text, with their columns
+fn open_delimiters(text: &str, delimiters: &Delimiters) -> Vec<(usize, usize)> {
+	let mut checker = LineChecker::default();
+	checker.check(text.as_bytes(), delimiters);
+	checker
+		.stack
+		.into_iter()
+		.map(|(pair, offset)| (pair, text[..offset].chars().count()))
+		.collect()
+}
+
+// a line of markers below the given columns, e.g., "   -    ^^"
+fn underline(marks: &[(usize, usize, char)]) -> String {
+	let mut line = String::new();
+	for &(column, width, mark) in marks.iter().sorted() {
+		let current = line.chars().count();
+		line.push_str(&" ".repeat(column.saturating_sub(current)));
+		line.push_str(&mark.to_string().repeat(width));
+	}
+	line
+}
+
+// explains what is wrong with a line, using the span of the chumsky error
+fn diagnose(
+	line: &str,
+	parser: &impl Parser<Lexeme, String, Error = Simple<Lexeme>>,
+	delimiters: &Delimiters,
+) -> Option<String> {
+	let (completion, errors) = parse_line(line, parser, delimiters);
+	let error = match errors.first() {
+		Some(error) => error,
+		None => {
+			let completion = completion.filter(|completion| !completion.is_empty())?;
+			return Some(format!(
+				"incomplete, missing `{}`\n    {}{}\n    {}",
+				completion,
+				line,
+				completion,
+				underline(&[(line.chars().count(), completion.chars().count(), '+')])
+			));
+		}
+	};
+	let column = error.span().start;
+	let offset = line
+		.char_indices()
+		.nth(column)
+		.map_or(line.len(), |(offset, _)| offset);
+	let open = open_delimiters(&line[..offset], delimiters);
+	let found = line[offset..]
+		.chars()
+		.next()
+		.map_or_else(|| "end of line".to_owned(), |found| format!("`{}`", found));
+
+	let closer = delimiters.closer_at(&line.as_bytes()[offset..]);
+	let message = match (closer, open.last()) {
+		(Some(closer), Some(&(pair, opened_at))) => {
+			let pair = &delimiters.pairs[pair];
+			let closer = &delimiters.pairs[closer].close;
+			return Some(format!(
+				"column {}: expected `{}` to close `{}` from column {}, found `{}`\n    {}\n    {}",
+				column + 1,
+				pair.close,
+				pair.open,
+				opened_at + 1,
+				closer,
+				line,
+				underline(&[
+					(opened_at, pair.open.chars().count(), '-'),
+					(column, closer.chars().count(), '^'),
+				])
+			));
+		}
+		(Some(closer), None) => format!(
+			"unexpected `{}`, nothing to close",
+			delimiters.pairs[closer].close
+		),
+		(None, _) => format!("unexpected {}", found),
+	};
+	Some(format!(
+		"column {}: {}\n    {}\n    {}",
+		column + 1,
+		message,
+		line,
+		underline(&[(column, 1, '^')])
+	))
+}
+
+// the score in the middle, as if sorted
+fn median(mut scores: Vec<usize>) -> Result<usize, Error> {
+	if scores.is_empty() {
+		return Err(NoIncompleteLines);
+	}
+	let middle = scores.len() / 2;
+	Ok(*scores.select_nth_unstable(middle).1)
+}
+
+// exact median in bounded memory, as long as the median stays within a window
+// of retained values; everything below or above the window is only counted
+struct WindowedMedian {
+	capacity: usize,
+	window: Vec<usize>,
+	below: usize,
+	above: usize,
+	// inclusive bounds of the window, once it has been trimmed
+	bounds: Option<(usize, usize)>,
+}
+
+impl WindowedMedian {
+	fn new(capacity: usize) -> Self {
+		WindowedMedian {
+			capacity: capacity.max(2),
+			window: Vec::new(),
+			below: 0,
+			above: 0,
+			bounds: None,
+		}
+	}
+
+	fn len(&self) -> usize {
+		self.below + self.window.len() + self.above
+	}
+
+	fn push(&mut self, value: usize) {
+		match self.bounds {
+			Some((lo, _)) if value < lo => self.below += 1,
+			Some((_, hi)) if value > hi => self.above += 1,
+			_ => self.window.push(value),
+		}
+		if self.window.len() > self.capacity {
+			self.trim();
+		}
+	}
+
+	// keeps the half of the window centered on the current median, which moves
+	// by at most half a rank per value pushed afterwards
+	fn trim(&mut self) {
+		let keep = self.capacity / 2;
+		let median = (self.len() / 2).clamp(self.below, self.below + self.window.len() - 1);
+		let start = (median - self.below)
+			.saturating_sub(keep / 2)
+			.min(self.window.len() - keep);
+		let end = start + keep;
+		if end < self.window.len() {
+			self.window.select_nth_unstable(end);
+			self.above += self.window.len() - end;
+			self.window.truncate(end);
+		}
+		if start > 0 {
+			self.window.select_nth_unstable(start);
+			self.below += start;
+			self.window.drain(..start);
+		}
+		let lo = *self.window.iter().min().unwrap();
+		let hi = *self.window.iter().max().unwrap();
+		self.bounds = Some((lo, hi));
+	}
+
+	// same as sorting all values and taking the one at len / 2
+	fn median(mut self) -> Result<usize, Error> {
+		if self.len() == 0 {
+			return Err(NoIncompleteLines);
+		}
+		let index = (self.len() / 2)
+			.checked_sub(self.below)
+			.filter(|&index| index < self.window.len())
+			.ok_or(MedianOutsideWindow)?;
+		Ok(*self.window.select_nth_unstable(index).1)
+	}
+}
+
+// the byte-stack checker, reading from any source in chunks, so that neither
+// the input nor the completion scores have to fit into memory
+fn check_stream(
+	mut reader: impl Read,
+	delimiters: &Delimiters,
+	capacity: usize,
+) -> Result<(usize, usize)> {
+	// a delimiter may straddle two chunks, so a short tail is carried over
+	let carry = delimiters.max_token_length() - 1;
+	let mut buffer = vec![0; (1 << 16) + carry];
+	let mut filled = 0;
+	let mut error_score = 0;
+	let mut recovery_scores = WindowedMedian::new(capacity);
+	let mut checker = LineChecker::default();
+	loop {
+		let read = reader.read(&mut buffer[filled..])?;
+		let eof = read == 0;
+		filled += read;
+		let mut offset = 0;
+		loop {
+			let newline = buffer[offset..filled]
+				.iter()
+				.position(|&b| b == b'\n')
+				.map(|length| offset + length);
+			let line_end = newline.unwrap_or(filled);
+			// without a newline, the last delimiter might continue in the next chunk
+			let limit = match newline {
+				None if !eof => filled.saturating_sub(carry).max(offset),
+				_ => line_end,
+			};
+			while offset < limit && !checker.is_corrupted() {
+				offset += checker.feed(&buffer[offset..line_end], delimiters);
+			}
+			let newline = match newline {
+				Some(newline) => newline,
+				None => {
+					offset = offset.max(limit);
+					break;
 				}
-			} else {
-				unreachable!();
+			};
+			error_score += checker.error_score(delimiters);
+			if let Some(score) = checker.completion_score(delimiters) {
+				recovery_scores.push(score);
 			}
-		})
-		.sum::<usize>();
-	println!("{}", syntax_error_score);
+			checker.reset();
+			offset = newline + 1;
+		}
+		if eof {
+			break;
+		}
+		buffer.copy_within(offset..filled, 0);
+		filled -= offset;
+	}
+	// the last line may lack a newline
+	error_score += checker.error_score(delimiters);
+	if let Some(score) = checker.completion_score(delimiters) {
+		recovery_scores.push(score);
+	}
+	Ok((error_score, recovery_scores.median()?))
+}
 
-	let mid = Instant::now();
+fn main() -> Result<()> {
+	color_eyre::install()?;
+	let mut print_diagnostics = false;
+	let mut delimiters_file = None;
+	let mut input_file = None;
+	let mut stream_file = None;
+	let mut median_capacity = 1 << 16;
+	let mut args = std::env::args().skip(1);
+	while let Some(arg) = args.next() {
+		let mut value = || args.next().ok_or_else(|| eyre!("{} requires a value", arg));
+		match arg.as_str() {
+			"--delimiters" => delimiters_file = Some(value()?),
+			"--input" => input_file = Some(value()?),
+			"--diagnostics" => print_diagnostics = true,
+			"--stream" => stream_file = Some(value()?),
+			"--median-capacity" => median_capacity = value()?.parse()?,
+			_ => return Err(eyre!("unknown argument {}", arg)),
+		}
+	}
+	let delimiters = match delimiters_file {
+		Some(file_name) => Delimiters::parse(&read_to_string(file_name)?)?,
+		None => Delimiters::parse(NAVIGATION_DELIMITERS)?,
+	};
 
-	let mut recovery_scores = Vec::new();
-	let mut line_recovered = String::new();
-	for line in INPUT.lines().filter(|&line| {
-		if let Err(simple) = parser().parse(line) {
-			assert!(simple.len() == 1);
-			let simple = simple.first().unwrap();
-			simple.reason() == &chumsky::error::SimpleReason::Unexpected && simple.found() == None
+	// streaming mode for inputs that don't fit into memory, - reads stdin
+	if let Some(file_name) = stream_file {
+		let start = Instant::now();
+		let (error_score, median_recovery_score) = if file_name == "-" {
+			check_stream(io::stdin().lock(), &delimiters, median_capacity)?
 		} else {
-			false
-		}
-	}) {
-		line_recovered.clear();
-		line_recovered.push_str(line);
-		while let Err(simple) = parser().parse(line_recovered.as_str()) {
-			assert!(simple.len() == 1);
-			let simple = simple.first().unwrap();
-			if simple.reason() == &chumsky::error::SimpleReason::Unexpected
-				&& simple.found() == None
-			{
-				for c in simple.expected() {
-					if matches!(c, ')' | ']' | '}' | '>') {
-						line_recovered.push(*c);
-						break;
-					}
-				}
-			} else {
-				unreachable!();
+			check_stream(File::open(file_name)?, &delimiters, median_capacity)?
+		};
+		let end = Instant::now();
+		println!("{}", error_score);
+		println!("{}", median_recovery_score);
+		println!("{} ms", (end - start).as_millis());
+		return Ok(());
+	}
+
+	let input = match input_file {
+		Some(file_name) => read_to_string(file_name)?,
+		None => INPUT.to_owned(),
+	};
+
+	let parser = parser_with_recovery(&delimiters);
+	if print_diagnostics {
+		for (number, line) in input.lines().enumerate() {
+			if let Some(diagnostic) = diagnose(line, &parser, &delimiters) {
+				println!("line {}, {}", number + 1, diagnostic);
 			}
 		}
-		let recovery = line_recovered.strip_prefix(line).unwrap();
-		let recovery_score = recovery
-			.chars()
-			.map(|c| match c {
-				')' => 1,
-				']' => 2,
-				'}' => 3,
-				'>' => 4,
-				_ => unreachable!(),
-			})
-			.fold(0usize, |old, new| old * 5 + new);
-		recovery_scores.push(recovery_score);
+	}
 
-		// println!("{}", recovery);
-		// println!("{:?}", parser_with_recovery().parse_recovery_verbose(line));
-		// todo!();
+	let start = Instant::now();
+
+	// both parts in a single parse per line
+	let mut syntax_error_score = 0;
+	let mut recovery_scores = Vec::new();
+	for line in input.lines() {
+		let (completion, errors) = parse_line(line, &parser, &delimiters);
+		if let Some(error) = errors.first() {
+			if error.found().is_some() {
+				let offset = line
+					.char_indices()
+					.nth(error.span().start)
+					.map_or(line.len(), |(offset, _)| offset);
+				// a closer with nothing open is corrupted too, but scores nothing
+				let closer = delimiters
+					.closer_at(&line.as_bytes()[offset..])
+					.filter(|_| !open_delimiters(&line[..offset], &delimiters).is_empty());
+				if let Some(closer) = closer {
+					syntax_error_score += delimiters.pairs[closer].corruption_score;
+				}
+			}
+		} else if let Some(completion) = completion.filter(|completion| !completion.is_empty()) {
+			let mut completion = completion.as_bytes();
+			recovery_scores.push(delimiters.completion_score(std::iter::from_fn(|| {
+				let closer = delimiters.closer_at(completion)?;
+				completion = &completion[delimiters.pairs[closer].close.len()..];
+				Some(closer)
+			})));
+		}
 	}
-	recovery_scores.sort_unstable();
-	let median_recovery_score = recovery_scores[recovery_scores.len() / 2];
+	let median_recovery_score = median(recovery_scores)?;
+	println!("{}", syntax_error_score);
 	println!("{}", median_recovery_score);
 
 	let end = Instant::now();
 
 	// excessively optimized version
-	let opening = [b'(', b'[', b'{', b'<'];
-	let closing = [b')', b']', b'}', b'>'];
 	let mut error_score = 0usize;
 	let mut recovery_scores = Vec::with_capacity(128);
-	let mut stack = Vec::with_capacity(128);
-	'lines: for line in INPUT
+	let mut checker = LineChecker::default();
+	for line in input
 		.as_bytes()
 		.split(|&b| b == b'\n')
 		.filter(|line| !line.is_empty())
 	{
-		stack.clear();
-		for c in line.iter().copied() {
-			if opening.contains(&c) {
-				stack.push(c);
-			} else if let Some(o) = stack.pop() {
-				let ci = closing.iter().position(|&cc| cc == c).unwrap();
-				if opening[ci] != o {
-					error_score += match c {
-						b')' => 3,
-						b']' => 57,
-						b'}' => 1197,
-						b'>' => 25137,
-						_ => unreachable!(),
-					};
-					continue 'lines;
-				}
-			} else {
-				continue 'lines;
-			}
-		}
-		let recovery_score = stack
-			.iter()
-			.rev()
-			.map(|&c| match c {
-				b'(' => 1,
-				b'[' => 2,
-				b'{' => 3,
-				b'<' => 4,
-				_ => unreachable!(),
-			})
-			.fold(0usize, |old, new| old * 5 + new);
-		recovery_scores.push(recovery_score);
+		checker.check(line, &delimiters);
+		error_score += checker.error_score(&delimiters);
+		recovery_scores.extend(checker.completion_score(&delimiters));
 	}
-	recovery_scores.sort_unstable();
-	let median_recovery_score = recovery_scores[recovery_scores.len() / 2];
+	let median_recovery_score = median(recovery_scores)?;
 
 	// fast enough that time is dominated by printing
 	let end_optimized_no_print = Instant::now();
@@ -193,8 +625,8 @@ fn main() {
 	println!("{}", median_recovery_score);
 	let end_optimized = Instant::now();
 
-	println!("{} ms", (mid - start).as_millis());
-	println!("{} ms", (end - mid).as_millis());
+	println!("{} ms", (end - start).as_millis());
 	println!("{} μs", (end_optimized_no_print - end).as_micros());
 	println!("{} μs", (end_optimized - end).as_micros());
+	Ok(())
 }
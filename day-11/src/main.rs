@@ -1,55 +1,346 @@
-use color_eyre::Result;
+use color_eyre::{eyre::eyre, Result};
 use common::read_digit_field;
 use ndarray::{prelude::*, IntoDimension};
+use std::{fmt, time::Instant};
 
-fn update(state: &mut Array2<u8>, flash_map: &mut Array2<u8>) -> usize {
-	let shape = state.raw_dim().into_dimension();
-	let mut flash_count = 0;
-	flash_map.fill(0);
-	*state += 1;
-	while {
-		let mut new_flashes = 0;
-		for (r, c) in ndarray::indices(shape) {
-			if flash_map[[r, c]] != 0 || state[[r, c]] <= 9 {
-				continue;
-			}
-			new_flashes += 1;
-			flash_map[[r, c]] = 1;
-			for r_off in [-1, 0, 1] {
-				for c_off in [-1, 0, 1] {
-					let r = r as isize + r_off;
-					let c = c as isize + c_off;
-					if !(0..shape[0] as isize).contains(&r) || !(0..shape[1] as isize).contains(&c)
-					{
-						continue;
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Boundary {
+	// excitation leaving the grid is lost
+	Absorbing,
+	// the grid wraps around like a torus
+	Periodic,
+}
+
+// all offsets within the given Chebyshev distance, excluding the center
+fn moore(radius: isize) -> Vec<(isize, isize)> {
+	(-radius..=radius)
+		.flat_map(|r| (-radius..=radius).map(move |c| (r, c)))
+		.filter(|&offset| offset != (0, 0))
+		.collect()
+}
+
+// all offsets within the given Manhattan distance, excluding the center
+fn von_neumann(radius: isize) -> Vec<(isize, isize)> {
+	moore(radius)
+		.into_iter()
+		.filter(|&(r, c)| r.abs() + c.abs() <= radius)
+		.collect()
+}
+
+// an excitable medium: every step, all cells gain energy, cells above the
+// threshold flash (at most once per step) and excite their neighbors, which
+// may cascade, and finally all flashed cells are reset
+struct Rules {
+	increment: Box<dyn Fn(u8) -> u8>,
+	threshold: u8,
+	neighborhood: Vec<(isize, isize)>,
+	// energy passed on to each neighbor of a flashing cell
+	excitation: u8,
+	boundary: Boundary,
+	reset: Box<dyn Fn(u8) -> u8>,
+}
+
+impl Rules {
+	// the dumbo octopuses of day 11
+	fn octopus() -> Self {
+		Rules {
+			increment: Box::new(|energy| energy + 1),
+			threshold: 9,
+			neighborhood: moore(1),
+			excitation: 1,
+			boundary: Boundary::Absorbing,
+			reset: Box::new(|_| 0),
+		}
+	}
+
+	fn neighbor(
+		&self,
+		index: (usize, usize),
+		offset: (isize, isize),
+		shape: (usize, usize),
+	) -> Option<(usize, usize)> {
+		let r = index.0 as isize + offset.0;
+		let c = index.1 as isize + offset.1;
+		let (rows, columns) = (shape.0 as isize, shape.1 as isize);
+		match self.boundary {
+			Boundary::Absorbing if (0..rows).contains(&r) && (0..columns).contains(&c) => {
+				Some((r as usize, c as usize))
+			}
+			Boundary::Absorbing => None,
+			Boundary::Periodic => {
+				Some((r.rem_euclid(rows) as usize, c.rem_euclid(columns) as usize))
+			}
+		}
+	}
+
+	// advances the state by one step, returns the number of flashes and marks
+	// flashed cells with 1 in the flash map
+	fn step(&self, state: &mut Array2<u8>, flash_map: &mut Array2<u8>) -> usize {
+		let shape = state.dim();
+		flash_map.fill(0);
+		state.mapv_inplace(&self.increment);
+		// cells are marked as soon as they exceed the threshold, so each one
+		// enters the worklist at most once and a step costs O(cells + flashes)
+		let mut worklist = Vec::new();
+		for (index, &energy) in state.indexed_iter() {
+			if energy > self.threshold {
+				flash_map[index] = 1;
+				worklist.push(index);
+			}
+		}
+		let mut flash_count = 0;
+		while let Some(index) = worklist.pop() {
+			flash_count += 1;
+			for &offset in &self.neighborhood {
+				if let Some(neighbor) = self.neighbor(index, offset, shape) {
+					state[neighbor] = state[neighbor].saturating_add(self.excitation);
+					if flash_map[neighbor] == 0 && state[neighbor] > self.threshold {
+						flash_map[neighbor] = 1;
+						worklist.push(neighbor);
 					}
-					state[[r as _, c as _]] += 1;
 				}
 			}
 		}
-		flash_count += new_flashes;
-		new_flashes != 0
-	} {}
-	for (r, c) in ndarray::indices(shape) {
-		if state[[r, c]] > 9 {
-			state[[r, c]] = 0;
+		self.reset(state, flash_map);
+		flash_count
+	}
+
+	// the original implementation, which rescans the whole grid until no new
+	// flashes occur, kept for comparison
+	fn step_rescanning(&self, state: &mut Array2<u8>, flash_map: &mut Array2<u8>) -> usize {
+		let shape = state.raw_dim().into_dimension();
+		let mut flash_count = 0;
+		flash_map.fill(0);
+		state.mapv_inplace(&self.increment);
+		while {
+			let mut new_flashes = 0;
+			for (r, c) in ndarray::indices(shape) {
+				if flash_map[[r, c]] != 0 || state[[r, c]] <= self.threshold {
+					continue;
+				}
+				new_flashes += 1;
+				flash_map[[r, c]] = 1;
+				for &offset in &self.neighborhood {
+					if let Some(neighbor) = self.neighbor((r, c), offset, state.dim()) {
+						state[neighbor] = state[neighbor].saturating_add(self.excitation);
+					}
+				}
+			}
+			flash_count += new_flashes;
+			new_flashes != 0
+		} {}
+		self.reset(state, flash_map);
+		flash_count
+	}
+
+	fn reset(&self, state: &mut Array2<u8>, flash_map: &Array2<u8>) {
+		azip!((energy in state, &flashed in flash_map) {
+			if flashed != 0 {
+				*energy = (self.reset)(*energy);
+			}
+		});
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Synchronization {
+	// first step in which all cells flash
+	At(usize),
+	// the simulation entered a cycle without all cells flashing at once
+	Never,
+	// neither happened within the step limit
+	Unknown(usize),
+}
+
+impl fmt::Display for Synchronization {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Synchronization::At(step) => write!(f, "{}", step),
+			Synchronization::Never => write!(f, "never synchronizes"),
+			Synchronization::Unknown(limit) => {
+				write!(f, "no synchronization within {} steps", limit)
+			}
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Cycle {
+	// steps before the state first repeats
+	pre_period: usize,
+	period: usize,
+	flashes_per_cycle: usize,
+}
+
+// the state space is finite and every step is deterministic, so the simulation
+// eventually enters a cycle, which Brent's algorithm finds while keeping only
+// two states around, instead of every state seen
+fn analyze(
+	rules: &Rules,
+	initial_state: &Array2<u8>,
+	step_limit: usize,
+) -> (Synchronization, Option<Cycle>) {
+	let mut flash_map = Array2::<u8>::zeros(initial_state.raw_dim());
+	let mut synchronization = None;
+	// the hare visits every state in order, while the tortoise waits at powers
+	// of two, until they meet, which gives the period; by then, the hare has
+	// gone through the whole cycle once
+	let mut tortoise = initial_state.clone();
+	let mut hare = initial_state.clone();
+	let mut power = 1;
+	let mut period = 0;
+	let mut step = 0;
+	loop {
+		if step == step_limit {
+			return (
+				synchronization.unwrap_or(Synchronization::Unknown(step_limit)),
+				None,
+			);
 		}
+		rules.step(&mut hare, &mut flash_map);
+		step += 1;
+		period += 1;
+		if synchronization.is_none() && flash_map.iter().all(|&flashed| flashed != 0) {
+			synchronization = Some(Synchronization::At(step));
+		}
+		if hare == tortoise {
+			break;
+		}
+		if period == power {
+			tortoise.assign(&hare);
+			power *= 2;
+			period = 0;
+		}
+	}
+	// with the hare a period ahead, both meet at the first repeated state
+	tortoise.assign(initial_state);
+	hare.assign(initial_state);
+	for _ in 0..period {
+		rules.step(&mut hare, &mut flash_map);
+	}
+	let mut pre_period = 0;
+	while tortoise != hare {
+		rules.step(&mut tortoise, &mut flash_map);
+		rules.step(&mut hare, &mut flash_map);
+		pre_period += 1;
+	}
+	let flashes_per_cycle = (0..period)
+		.map(|_| rules.step(&mut tortoise, &mut flash_map))
+		.sum();
+	let cycle = Cycle {
+		pre_period,
+		period,
+		flashes_per_cycle,
+	};
+	(
+		synchronization.unwrap_or(Synchronization::Never),
+		Some(cycle),
+	)
+}
+
+// random energy levels from a xorshift generator, no need for the rand crate
+fn generate_grid(size: usize, mut seed: u64) -> Array2<u8> {
+	Array2::from_shape_simple_fn((size, size), || {
+		seed ^= seed << 13;
+		seed ^= seed >> 7;
+		seed ^= seed << 17;
+		(seed % 10) as u8
+	})
+}
+
+// both implementations on a large random grid, which must agree exactly
+fn benchmark(rules: &Rules, size: usize, steps: usize) -> Result<()> {
+	let initial_state = generate_grid(size, 0x2021_1211);
+	let mut timings = Vec::new();
+	let mut results = Vec::new();
+	for (name, step) in [
+		(
+			"worklist",
+			Rules::step as fn(&Rules, &mut Array2<u8>, &mut Array2<u8>) -> usize,
+		),
+		("rescanning", Rules::step_rescanning),
+	] {
+		let mut state = initial_state.clone();
+		let mut flash_map = Array2::<u8>::zeros(state.raw_dim());
+		let start = Instant::now();
+		let flashes: usize = (0..steps)
+			.map(|_| step(rules, &mut state, &mut flash_map))
+			.sum();
+		timings.push((name, Instant::now() - start));
+		results.push((flashes, state));
+	}
+	if results[0] != results[1] {
+		return Err(eyre!("implementations disagree"));
 	}
-	flash_count
+	println!(
+		"{}×{} grid, {} steps, {} flashes",
+		size, size, steps, results[0].0
+	);
+	for (name, timing) in timings {
+		println!("{}: {} ms", name, timing.as_millis());
+	}
+	Ok(())
 }
 
 fn main() -> Result<()> {
 	color_eyre::install()?;
-	let initial_state = read_digit_field("day-11/input")?;
+	let mut rules = Rules::octopus();
+	let mut input_file = None;
+	let mut benchmark_size = None;
+	let mut print_cycle = false;
+	let mut step_limit = 100_000;
+	let mut args = std::env::args().skip(1);
+	while let Some(arg) = args.next() {
+		let mut value = || args.next().ok_or_else(|| eyre!("{} requires a value", arg));
+		match arg.as_str() {
+			"--input" => input_file = Some(value()?),
+			"--benchmark" => benchmark_size = Some(value()?.parse()?),
+			"--cycle" => print_cycle = true,
+			"--step-limit" => step_limit = value()?.parse()?,
+			// variations of the octopus rules, to play around with
+			"--rules" => {
+				rules = match value()?.as_str() {
+					"octopus" => Rules::octopus(),
+					"torus" => Rules {
+						boundary: Boundary::Periodic,
+						..Rules::octopus()
+					},
+					"cross" => Rules {
+						neighborhood: von_neumann(1),
+						..Rules::octopus()
+					},
+					"leaky" => Rules {
+						reset: Box::new(|energy| energy / 4),
+						..Rules::octopus()
+					},
+					other => return Err(eyre!("unknown rules {}", other)),
+				}
+			}
+			_ => return Err(eyre!("unknown argument {}", arg)),
+		}
+	}
+	if let Some(size) = benchmark_size {
+		return benchmark(&rules, size, 100);
+	}
+	let initial_state = read_digit_field(input_file.as_deref().unwrap_or("day-11/input"))?;
+
 	let mut state = initial_state.clone();
 	let mut flash_map = Array2::<u8>::zeros(state.raw_dim());
-	let flashes: usize = (0..100).map(|_| update(&mut state, &mut flash_map)).sum();
+	let flashes: usize = (0..100)
+		.map(|_| rules.step(&mut state, &mut flash_map))
+		.sum();
 	println!("{}", flashes);
-	let mut state = initial_state;
-	let synchronous_step = (1..).find(|_| {
-		update(&mut state, &mut flash_map);
-		flash_map.as_slice().unwrap().iter().all(|f| *f == 1)
-	});
-	println!("{}", synchronous_step.unwrap());
+
+	let (synchronization, cycle) = analyze(&rules, &initial_state, step_limit);
+	println!("{}", synchronization);
+	if print_cycle {
+		match cycle {
+			Some(cycle) => println!(
+				"cycle after {} steps, period {}, {} flashes per cycle",
+				cycle.pre_period, cycle.period, cycle.flashes_per_cycle
+			),
+			None => println!("no cycle within {} steps", step_limit),
+		}
+	}
 	Ok(())
 }
@@ -1,11 +1,20 @@
+use color_eyre::{eyre::eyre, Result};
 use itertools::Itertools;
-use petgraph::graph::{NodeIndex, UnGraph};
-use petgraph::visit::EdgeRef;
-use std::collections::HashMap;
+use petgraph::{
+	graph::{EdgeIndex, Neighbors, NodeIndex, UnGraph},
+	visit::EdgeRef,
+};
+use std::{
+	collections::HashMap,
+	fs::File,
+	io::{BufWriter, Write},
+};
 
 static INPUT: &str = include_str!("../input");
 
-fn parse_graph() -> UnGraph<&'static str, u32> {
+type Caves = UnGraph<&'static str, u32>;
+
+fn parse_graph() -> Caves {
 	let mut graph = UnGraph::new_undirected();
 
 	// ensure nodes aren't added twice
@@ -30,7 +39,7 @@ fn parse_graph() -> UnGraph<&'static str, u32> {
 	graph
 }
 
-fn is_small_cave(node: NodeIndex, graph: &UnGraph<&'static str, u32>) -> bool {
+fn is_small_cave(node: NodeIndex, graph: &Caves) -> bool {
 	graph
 		.node_weight(node)
 		.unwrap()
@@ -38,79 +47,451 @@ fn is_small_cave(node: NodeIndex, graph: &UnGraph<&'static str, u32>) -> bool {
 		.all(char::is_lowercase)
 }
 
-fn find_paths(
-	current: NodeIndex,
-	partial: &mut Vec<NodeIndex>,
-	paths: &mut Vec<Vec<NodeIndex>>,
-	graph: &UnGraph<&'static str, u32>,
-) {
-	// end reached
-	if current == 1.into() {
-		partial.push(current);
-		paths.push(partial.clone());
-		let _ = partial.pop();
-		return;
+// which caves may be entered how often: small caves once, except for up to
+// `revisited_caves` of them, which may be visited up to `max_visits` times,
+// unless overridden by a per-cave limit, and all required caves must be visited
+#[derive(Clone, Debug)]
+struct VisitPolicy {
+	revisited_caves: usize,
+	max_visits: u8,
+	// also applies to big caves, a limit of 0 forbids a cave
+	limits: HashMap<NodeIndex, u8>,
+	required: Vec<NodeIndex>,
+}
+
+impl VisitPolicy {
+	fn part_one() -> Self {
+		VisitPolicy {
+			revisited_caves: 0,
+			max_visits: 1,
+			limits: HashMap::new(),
+			required: Vec::new(),
+		}
+	}
+
+	// a single small cave may be visited twice, but start never
+	fn part_two() -> Self {
+		VisitPolicy {
+			revisited_caves: 1,
+			max_visits: 2,
+			limits: HashMap::from([(0.into(), 1)]),
+			..Self::part_one()
+		}
+	}
+
+	// whether the cave may be entered once more, given the visits so far
+	fn allows(
+		&self,
+		node: NodeIndex,
+		visits: impl Fn(NodeIndex) -> u8 + Copy,
+		graph: &Caves,
+	) -> bool {
+		let count = visits(node);
+		if let Some(&limit) = self.limits.get(&node) {
+			return count < limit;
+		}
+		if !is_small_cave(node, graph) || count == 0 {
+			return true;
+		}
+		if count >= self.max_visits {
+			return false;
+		}
+		// caves visited more than once already count towards the revisited caves
+		count > 1 || self.revisited(visits, graph) < self.revisited_caves
 	}
 
-	// small caves should only be visited once
-	if is_small_cave(current, graph) && partial.contains(&current) {
-		return;
+	fn revisited(&self, visits: impl Fn(NodeIndex) -> u8, graph: &Caves) -> usize {
+		graph
+			.node_indices()
+			.filter(|&node| {
+				visits(node) > 1 && is_small_cave(node, graph) && !self.limits.contains_key(&node)
+			})
+			.count()
 	}
 
-	partial.push(current);
-	for edge in graph.edges(current) {
-		find_paths(edge.target(), partial, paths, graph);
+	fn satisfied(&self, visits: impl Fn(NodeIndex) -> u8) -> bool {
+		self.required.iter().all(|&node| visits(node) > 0)
 	}
-	let _ = partial.pop();
 }
 
-fn find_paths_task_two(
-	current: NodeIndex,
-	partial: &mut Vec<NodeIndex>,
-	paths: &mut Vec<Vec<NodeIndex>>,
-	graph: &UnGraph<&'static str, u32>,
-) {
-	// end reached
-	if current == 1.into() {
-		partial.push(current);
-		paths.push(partial.clone());
-		let _ = partial.pop();
-		return;
-	}
-
-	// small caves should only be visited once or twice
-	if is_small_cave(current, graph) {
-		let counts = partial
-			.iter()
-			.filter(|&&i| is_small_cave(i, graph))
-			.copied()
-			.counts();
-		if counts.contains_key(&current) && counts.values().any(|&c| c >= 2) {
-			return;
-		}
-	}
-
-	partial.push(current);
-	// start may only be visited once
-	for edge in graph.edges(current).filter(|i| i.target() != 0.into()) {
-		find_paths_task_two(edge.target(), partial, paths, graph);
-	}
-	let _ = partial.pop();
+// visit counts packed into a single integer, with a field just wide enough for
+// the limit of each limited cave and one bit for each other required cave,
+// while all remaining caves don't matter and are left out
+struct VisitLayout {
+	// bit offset and width of each cave's field
+	fields: Vec<Option<(u32, u32)>>,
 }
 
-fn main() {
+impl VisitLayout {
+	fn new(graph: &Caves, policy: &VisitPolicy) -> Result<Self> {
+		let mut offset = 0;
+		let mut fields = Vec::with_capacity(graph.node_count());
+		for node in graph.node_indices() {
+			let limit = match policy.limits.get(&node) {
+				Some(&limit) => limit,
+				None if is_small_cave(node, graph) => policy.max_visits,
+				None if policy.required.contains(&node) => 1,
+				None => {
+					fields.push(None);
+					continue;
+				}
+			};
+			let width = (u8::BITS - limit.leading_zeros()).max(1);
+			fields.push(Some((offset, width)));
+			offset += width;
+		}
+		if offset > u128::BITS {
+			return Err(eyre!(
+				"too many limited caves to pack their visits into 128 bits"
+			));
+		}
+		Ok(VisitLayout { fields })
+	}
+
+	fn get(&self, visits: u128, node: NodeIndex) -> u8 {
+		self.fields[node.index()].map_or(0, |(offset, width)| {
+			(visits >> offset & ((1 << width) - 1)) as u8
+		})
+	}
+
+	// counts of required but otherwise unlimited caves stay at 1
+	fn visit(&self, visits: u128, node: NodeIndex) -> u128 {
+		match self.fields[node.index()] {
+			Some((offset, width)) => {
+				let mask = (1 << width) - 1;
+				let count = (self.get(visits, node) as u128 + 1).min(mask);
+				visits & !(mask << offset) | count << offset
+			}
+			None => visits,
+		}
+	}
+}
+
+// the number of paths only depends on the current cave and how often each
+// cave has been visited so far, not on the order of the visits, so it can be
+// memoized instead of enumerating every path
+struct PathCounter<'a> {
+	graph: &'a Caves,
+	policy: &'a VisitPolicy,
+	layout: VisitLayout,
+	// None while being computed, to detect cycles of unlimited caves
+	memo: HashMap<(NodeIndex, u128), Option<u64>>,
+	// states in the order their counts were completed, i.e., after all of their
+	// successors, which makes the reverse a topological order
+	finished: Vec<(NodeIndex, u128)>,
+}
+
+impl<'a> PathCounter<'a> {
+	fn new(graph: &'a Caves, policy: &'a VisitPolicy) -> Result<Self> {
+		Ok(PathCounter {
+			graph,
+			policy,
+			layout: VisitLayout::new(graph, policy)?,
+			memo: HashMap::new(),
+			finished: Vec::new(),
+		})
+	}
+
+	fn count_from_start(&mut self) -> Result<u64> {
+		self.count(0.into(), 0)
+	}
+
+	fn count(&mut self, current: NodeIndex, visits: u128) -> Result<u64> {
+		let layout = &self.layout;
+		if !self
+			.policy
+			.allows(current, |node| layout.get(visits, node), self.graph)
+		{
+			return Ok(0);
+		}
+		let visits = layout.visit(visits, current);
+
+		// end reached
+		if current == 1.into() {
+			return Ok(self.policy.satisfied(|node| layout.get(visits, node)) as u64);
+		}
+
+		let key = (current, visits);
+		match self.memo.get(&key) {
+			Some(Some(count)) => return Ok(*count),
+			Some(None) => return Err(eyre!("infinitely many paths, unlimited caves are adjacent")),
+			None => {}
+		}
+		self.memo.insert(key, None);
+		let mut count = 0u64;
+		for next in self.graph.neighbors(current) {
+			count = count
+				.checked_add(self.count(next, visits)?)
+				.ok_or_else(|| eyre!("number of paths exceeds u64"))?;
+		}
+		self.memo.insert(key, Some(count));
+		self.finished.push(key);
+		Ok(count)
+	}
+
+	// the number of times each edge is traversed by all paths, without
+	// enumerating them: the paths into a state times the paths out of the
+	// following one, summed over all states the edge is taken from
+	fn edge_traffic(&mut self) -> Result<HashMap<EdgeIndex, u64>> {
+		self.count_from_start()?;
+		let start = (0.into(), self.layout.visit(0, 0.into()));
+		let mut paths_into = HashMap::from([(start, 1u64)]);
+		let mut traffic = HashMap::new();
+		for (current, visits) in std::mem::take(&mut self.finished).into_iter().rev() {
+			// states without any complete path never got a share
+			let paths_in = match paths_into.get(&(current, visits)) {
+				Some(&paths_in) => paths_in,
+				None => continue,
+			};
+			for edge in self.graph.edges(current) {
+				let next = edge.target();
+				let paths_out = self.count(next, visits)?;
+				if paths_out == 0 {
+					continue;
+				}
+				let count: &mut u64 = traffic.entry(edge.id()).or_default();
+				*count = paths_in
+					.checked_mul(paths_out)
+					.and_then(|paths| count.checked_add(paths))
+					.ok_or_else(|| eyre!("edge traffic exceeds u64"))?;
+				// every prefix into the next state has a complete path, so this
+				// can't exceed the number of paths
+				if next != 1.into() {
+					let key = (next, self.layout.visit(visits, next));
+					*paths_into.entry(key).or_default() += paths_in;
+				}
+			}
+		}
+		Ok(traffic)
+	}
+}
+
+// depth-first enumeration of the same paths, for when they're actually needed
+struct Paths<'a> {
+	graph: &'a Caves,
+	policy: &'a VisitPolicy,
+	// current path, with the neighbors of each cave that are yet to be tried
+	stack: Vec<(NodeIndex, Neighbors<'a, u32>)>,
+	visits: Vec<u8>,
+}
+
+impl<'a> Paths<'a> {
+	fn new(graph: &'a Caves, policy: &'a VisitPolicy) -> Self {
+		let mut paths = Paths {
+			graph,
+			policy,
+			stack: Vec::new(),
+			visits: vec![0; graph.node_count()],
+		};
+		paths.enter(0.into());
+		paths
+	}
+
+	fn enter(&mut self, node: NodeIndex) {
+		let visits = &self.visits;
+		if self
+			.policy
+			.allows(node, |node| visits[node.index()], self.graph)
+		{
+			self.visits[node.index()] += 1;
+			self.stack.push((node, self.graph.neighbors(node)));
+		}
+	}
+
+	fn leave(&mut self) {
+		if let Some((node, _)) = self.stack.pop() {
+			self.visits[node.index()] -= 1;
+		}
+	}
+}
+
+impl Iterator for Paths<'_> {
+	type Item = Vec<NodeIndex>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			let (current, neighbors) = self.stack.last_mut()?;
+			// end reached
+			if *current == 1.into() {
+				let path = self.stack.iter().map(|&(node, _)| node).collect_vec();
+				let visits = &self.visits;
+				let satisfied = self.policy.satisfied(|node| visits[node.index()]);
+				self.leave();
+				if satisfied {
+					return Some(path);
+				}
+				continue;
+			}
+			match neighbors.next() {
+				None => self.leave(),
+				Some(next) => self.enter(next),
+			}
+		}
+	}
+}
+
+// GraphViz export with small caves as circles and big caves as boxes, the
+// highlighted path in red and the most travelled edges labelled and thicker
+fn write_dot(
+	file_name: &str,
+	graph: &Caves,
+	path: &[NodeIndex],
+	travelled: &[(EdgeIndex, u64)],
+) -> Result<()> {
+	let mut file = BufWriter::new(File::create(file_name)?);
+	writeln!(file, "graph caves {{")?;
+	writeln!(file, "\tnode [fontname=\"Helvetica\"];")?;
+	for node in graph.node_indices() {
+		let shape = match graph[node] {
+			"start" | "end" => "doublecircle",
+			_ if is_small_cave(node, graph) => "circle",
+			_ => "box",
+		};
+		let style = if is_small_cave(node, graph) {
+			"solid"
+		} else {
+			"filled"
+		};
+		let color = if path.contains(&node) { "red" } else { "black" };
+		writeln!(
+			file,
+			"\t\"{}\" [shape={}, style={}, fillcolor=lightgray, color={}];",
+			graph[node], shape, style, color
+		)?;
+	}
+	let max_traffic = travelled.iter().map(|&(_, count)| count).max().unwrap_or(1);
+	for edge in graph.edge_references() {
+		let mut attributes = Vec::new();
+		let on_path = path.iter().tuple_windows().any(|(&a, &b)| {
+			(a, b) == (edge.source(), edge.target()) || (b, a) == (edge.source(), edge.target())
+		});
+		if on_path {
+			attributes.push("color=red".to_owned());
+		}
+		if let Some(&(_, count)) = travelled.iter().find(|&&(index, _)| index == edge.id()) {
+			attributes.push(format!("label=\"{}\"", count));
+			attributes.push(format!(
+				"penwidth={:.1}",
+				1.0 + 4.0 * count as f64 / max_traffic as f64
+			));
+		}
+		write!(
+			file,
+			"\t\"{}\" -- \"{}\"",
+			graph[edge.source()],
+			graph[edge.target()]
+		)?;
+		if !attributes.is_empty() {
+			write!(file, " [{}]", attributes.join(", "))?;
+		}
+		writeln!(file, ";")?;
+	}
+	writeln!(file, "}}")?;
+	Ok(())
+}
+
+fn main() -> Result<()> {
+	color_eyre::install()?;
 	let graph = parse_graph();
+	let find_cave = |name: &str| {
+		graph
+			.node_indices()
+			.find(|&node| graph[node] == name)
+			.ok_or_else(|| eyre!("unknown cave {}", name))
+	};
 
-	let mut paths = Vec::new();
-	find_paths(0.into(), &mut Vec::new(), &mut paths, &graph);
-	println!("{}", paths.len());
+	// a custom policy, for other routing questions
+	let mut custom = None;
+	let mut print_paths = false;
+	let mut dot_file = None;
+	let mut highlight = Vec::new();
+	let mut most_travelled = 0;
+	let mut args = std::env::args().skip(1);
+	while let Some(arg) = args.next() {
+		let mut value = || args.next().ok_or_else(|| eyre!("{} requires a value", arg));
+		match arg.as_str() {
+			"--paths" => print_paths = true,
+			"--dot" => dot_file = Some(value()?),
+			// a comma-separated path, e.g., start,A,b,end
+			"--highlight" => {
+				highlight = value()?
+					.split(',')
+					.map(&find_cave)
+					.collect::<Result<Vec<_>>>()?;
+				if let Some((a, b)) = highlight
+					.iter()
+					.tuple_windows()
+					.find(|&(&a, &b)| graph.find_edge(a, b).is_none())
+				{
+					return Err(eyre!("{} and {} are not connected", graph[*a], graph[*b]));
+				}
+			}
+			"--most-travelled" => most_travelled = value()?.parse()?,
+			"--revisits" => {
+				custom
+					.get_or_insert_with(VisitPolicy::part_one)
+					.revisited_caves = value()?.parse()?
+			}
+			"--max-visits" => {
+				custom.get_or_insert_with(VisitPolicy::part_one).max_visits = value()?.parse()?
+			}
+			"--limit" => {
+				let value = value()?;
+				let (name, limit) = value
+					.split_once('=')
+					.ok_or_else(|| eyre!("--limit requires CAVE=N"))?;
+				let cave = find_cave(name)?;
+				custom
+					.get_or_insert_with(VisitPolicy::part_one)
+					.limits
+					.insert(cave, limit.parse()?);
+			}
+			"--forbid" => {
+				let cave = find_cave(&value()?)?;
+				custom
+					.get_or_insert_with(VisitPolicy::part_one)
+					.limits
+					.insert(cave, 0);
+			}
+			"--via" => {
+				let cave = find_cave(&value()?)?;
+				custom
+					.get_or_insert_with(VisitPolicy::part_one)
+					.required
+					.push(cave);
+			}
+			_ => return Err(eyre!("unknown argument {}", arg)),
+		}
+	}
 
-	paths.clear();
-	find_paths_task_two(0.into(), &mut Vec::new(), &mut paths, &graph);
-	println!("{}", paths.len());
+	let policies = [
+		Some(VisitPolicy::part_one()),
+		Some(VisitPolicy::part_two()),
+		custom,
+	];
+	for policy in policies.iter().flatten() {
+		let count = PathCounter::new(&graph, policy)?.count_from_start()?;
+		println!("{}", count);
+		if print_paths {
+			for path in Paths::new(&graph, policy) {
+				println!("{}", path.iter().map(|&node| graph[node]).join(","));
+			}
+		}
+	}
 
-	println!(
-		"{}",
-		petgraph::dot::Dot::with_config(&graph, &[petgraph::dot::Config::EdgeNoLabel])
-	);
+	// edge traffic is based on the last policy, i.e., the custom one if given
+	if let Some(file_name) = dot_file {
+		let mut travelled = Vec::new();
+		if most_travelled > 0 {
+			let policy = policies.iter().flatten().last().unwrap();
+			travelled = PathCounter::new(&graph, policy)?
+				.edge_traffic()?
+				.into_iter()
+				.sorted_by_key(|&(edge, count)| (std::cmp::Reverse(count), edge))
+				.take(most_travelled)
+				.collect();
+		}
+		write_dot(&file_name, &graph, &highlight, &travelled)?;
+	}
+	Ok(())
 }
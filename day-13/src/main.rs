@@ -1,21 +1,39 @@
-use color_eyre::Result;
+use color_eyre::{eyre::eyre, Result};
 use itertools::Itertools;
 use std::{
 	borrow::BorrowMut,
-	fs::File,
-	io::{BufRead, BufReader},
+	collections::HashSet,
+	fmt,
+	fs::{read_to_string, File},
+	io::{BufRead, BufReader, BufWriter, Write},
 };
 use thiserror::Error;
 
+const GLYPH_WIDTH: usize = 4;
+const GLYPH_HEIGHT: usize = 6;
+// letters are separated by an empty column
+const GLYPH_PITCH: usize = GLYPH_WIDTH + 1;
+
+static LETTERS: &str = include_str!("../fonts/letters.txt");
+
 #[derive(Error, Debug)]
 enum Error {
 	#[error("incorrect input (point has != 2 values)")]
 	IncorrectPointLength,
 	#[error("incorrect input (instruction has incorrect format)")]
 	IncorrectInstructionFormat,
+	#[error("incorrect glyph format in font")]
+	IncorrectGlyphFormat,
+	#[error("text is {0} rows high instead of {GLYPH_HEIGHT}")]
+	IncorrectTextHeight(usize),
+	#[error("unknown glyph at position {0}:\n{1}")]
+	UnknownGlyph(usize, String),
 }
 
-use Error::{IncorrectInstructionFormat, IncorrectPointLength};
+use Error::{
+	IncorrectGlyphFormat, IncorrectInstructionFormat, IncorrectPointLength, IncorrectTextHeight,
+	UnknownGlyph,
+};
 
 enum Fold {
 	Horizontal(usize),
@@ -24,6 +42,133 @@ enum Fold {
 
 use Fold::{Horizontal, Vertical};
 
+impl fmt::Display for Fold {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Horizontal(x) => write!(f, "x={}", x),
+			Vertical(y) => write!(f, "y={}", y),
+		}
+	}
+}
+
+impl Fold {
+	fn line(&self) -> usize {
+		match *self {
+			Horizontal(x) => x,
+			Vertical(y) => y,
+		}
+	}
+
+	// the coordinate affected by the fold
+	fn coordinate<'a>(&self, point: &'a mut (usize, usize)) -> &'a mut usize {
+		match self {
+			Horizontal(_) => &mut point.0,
+			Vertical(_) => &mut point.1,
+		}
+	}
+
+	// folds everything past the line over to the other side, if that overhangs
+	// the origin, all points are shifted to keep coordinates non-negative,
+	// returns that shift
+	fn apply(&self, points: &mut Vec<(usize, usize)>) -> usize {
+		let line = self.line() as isize;
+		let folded = points
+			.iter_mut()
+			.map(|point| {
+				let coordinate = *self.coordinate(point) as isize;
+				if coordinate > line {
+					2 * line - coordinate
+				} else {
+					coordinate
+				}
+			})
+			.collect_vec();
+		let shift = folded.iter().min().map_or(0, |&min| (-min).max(0));
+		for (point, folded) in points.iter_mut().zip(folded) {
+			*self.coordinate(point) = (folded + shift) as usize;
+		}
+		points.sort_unstable();
+		points.dedup();
+		shift as usize
+	}
+
+	// every dot left of the line (or above it) may have been on either side or
+	// on both before the fold, while dots in the overhang must have been mirrored
+	fn unfold(&self, points: &[(usize, usize)], shift: usize) -> Unfolding {
+		let line = self.line() as isize;
+		let mut unfolding = Unfolding {
+			certain: Vec::new(),
+			either: Vec::new(),
+		};
+		for &point in points {
+			let mut near = point;
+			let mut far = point;
+			let coordinate = *self.coordinate(&mut near) as isize - shift as isize;
+			*self.coordinate(&mut far) = (2 * line - coordinate) as usize;
+			if coordinate < 0 {
+				unfolding.certain.push(far);
+			} else if coordinate == line {
+				unfolding.certain.push(near);
+			} else {
+				*self.coordinate(&mut near) = coordinate as usize;
+				unfolding.either.push((near, far));
+			}
+		}
+		unfolding
+	}
+}
+
+// all point sets which fold into the same points
+struct Unfolding {
+	certain: Vec<(usize, usize)>,
+	// at least one of each pair of points must have been there
+	either: Vec<((usize, usize), (usize, usize))>,
+}
+
+impl Unfolding {
+	// one side, the other or both for each pair
+	fn count(&self) -> Option<u128> {
+		3u128.checked_pow(self.either.len().try_into().ok()?)
+	}
+
+	fn contains(&self, points: &[(usize, usize)]) -> bool {
+		let points = points.iter().copied().collect::<HashSet<_>>();
+		let covered = self
+			.certain
+			.iter()
+			.chain(self.either.iter().flat_map(|(near, far)| [near, far]))
+			.collect::<HashSet<_>>();
+		self.certain.iter().all(|point| points.contains(point))
+			&& self
+				.either
+				.iter()
+				.all(|(near, far)| points.contains(near) || points.contains(far))
+			&& points.iter().all(|point| covered.contains(point))
+	}
+
+	// beware, there are exponentially many
+	fn sets(&self) -> impl Iterator<Item = Vec<(usize, usize)>> + '_ {
+		let choices = self.either.iter().map(|_| 0..3).collect_vec();
+		let empty = choices.is_empty();
+		choices
+			.into_iter()
+			.multi_cartesian_product()
+			.chain(empty.then(Vec::new))
+			.map(|choices| {
+				let mut points = self.certain.clone();
+				for (&(near, far), choice) in self.either.iter().zip(choices) {
+					match choice {
+						0 => points.push(near),
+						1 => points.push(far),
+						_ => points.extend([near, far]),
+					}
+				}
+				points.sort_unstable();
+				points
+			})
+	}
+}
+
 type Instructions = (Vec<(usize, usize)>, Vec<Fold>);
 
 fn read_input(file_name: &str) -> Result<Instructions> {
@@ -68,34 +213,100 @@ fn read_input(file_name: &str) -> Result<Instructions> {
 	Ok((points, instructions))
 }
 
-fn main() -> Result<()> {
-	color_eyre::install()?;
-	let (mut points, instructions) = read_input("day-13/input")?;
-	for instruction in instructions {
-		match instruction {
-			Horizontal(x) => {
-				for point in &mut points {
-					if point.0 >= x {
-						point.0 = x - (point.0 - x)
+// one bit per pixel, row by row
+type Glyph = u32;
+
+fn glyph_to_string(glyph: Glyph) -> String {
+	(0..GLYPH_HEIGHT)
+		.map(|row| {
+			(0..GLYPH_WIDTH)
+				.map(|column| {
+					if glyph & 1 << (row * GLYPH_WIDTH + column) != 0 {
+						'#'
+					} else {
+						'.'
 					}
+				})
+				.collect::<String>()
+		})
+		.join("\n")
+}
+
+struct Font {
+	glyphs: Vec<(char, Glyph)>,
+}
+
+impl Font {
+	// a symbol on a line by itself, followed by its rows of # and .
+	fn parse(text: &str) -> Result<Self, Error> {
+		let mut glyphs = Vec::new();
+		let mut lines = text
+			.lines()
+			.map(str::trim_end)
+			.filter(|line| !line.is_empty());
+		while let Some(symbol) = lines.next() {
+			let (symbol,) = symbol.chars().collect_tuple().ok_or(IncorrectGlyphFormat)?;
+			let mut glyph = 0;
+			for row in 0..GLYPH_HEIGHT {
+				let line = lines.next().ok_or(IncorrectGlyphFormat)?;
+				if line.len() != GLYPH_WIDTH {
+					return Err(IncorrectGlyphFormat);
 				}
-			}
-			Vertical(y) => {
-				for point in &mut points {
-					if point.1 >= y {
-						point.1 = y - (point.1 - y)
+				for (column, pixel) in line.bytes().enumerate() {
+					match pixel {
+						b'#' => glyph |= 1 << (row * GLYPH_WIDTH + column),
+						b'.' => {}
+						_ => return Err(IncorrectGlyphFormat),
 					}
 				}
 			}
+			glyphs.push((symbol, glyph));
 		}
-		points.sort_unstable();
-		points.dedup();
-		println!("{}", points.len());
+		Ok(Font { glyphs })
+	}
+
+	// glyphs of the other font come first, so they take precedence
+	fn extend(&mut self, other: Font) {
+		self.glyphs.splice(0..0, other.glyphs);
 	}
+
+	// reads the letters formed by the points, left to right
+	fn recognize(&self, points: &[(usize, usize)]) -> Result<String, Error> {
+		let height = points.iter().map(|point| point.1 + 1).max().unwrap_or(0);
+		if height != GLYPH_HEIGHT {
+			return Err(IncorrectTextHeight(height));
+		}
+		let width = points.iter().map(|point| point.0 + 1).max().unwrap_or(0);
+		let mut glyphs = vec![0; width.div_ceil(GLYPH_PITCH)];
+		for &(x, y) in points {
+			let column = x % GLYPH_PITCH;
+			if column == GLYPH_WIDTH {
+				return Err(UnknownGlyph(
+					x / GLYPH_PITCH,
+					"dot between glyphs".to_owned(),
+				));
+			}
+			glyphs[x / GLYPH_PITCH] |= 1 << (y * GLYPH_WIDTH + column);
+		}
+		glyphs
+			.into_iter()
+			.enumerate()
+			.map(|(index, glyph)| {
+				self.glyphs
+					.iter()
+					.find(|&&(_, other)| other == glyph)
+					.map(|&(symbol, _)| symbol)
+					.ok_or_else(|| UnknownGlyph(index, glyph_to_string(glyph)))
+			})
+			.collect()
+	}
+}
+
+fn render(points: &mut [(usize, usize)]) {
 	points.sort_by_key(|point| (point.1, point.0));
 	let mut x = 0;
 	let mut y = 0;
-	for point in points {
+	for point in points.iter() {
 		while y < point.1 {
 			println!();
 			y += 1;
@@ -108,5 +319,189 @@ fn main() -> Result<()> {
 		print!("█");
 		x += 1;
 	}
+	println!();
+}
+
+// the dots as a dense bitmap, for exports
+struct Bitmap {
+	width: usize,
+	height: usize,
+	pixels: Vec<bool>,
+}
+
+impl Bitmap {
+	fn from_points(points: &[(usize, usize)]) -> Self {
+		let width = points.iter().map(|point| point.0 + 1).max().unwrap_or(0);
+		let height = points.iter().map(|point| point.1 + 1).max().unwrap_or(0);
+		let mut pixels = vec![false; width * height];
+		for &(x, y) in points {
+			pixels[y * width + x] = true;
+		}
+		Bitmap {
+			width,
+			height,
+			pixels,
+		}
+	}
+
+	fn get(&self, x: usize, y: usize) -> bool {
+		y < self.height && self.pixels[y * self.width + x]
+	}
+
+	// rows of eight pixels per byte, most significant bit first
+	fn packed(&self, dot: bool) -> Vec<u8> {
+		let mut bytes = Vec::with_capacity(self.height * self.width.div_ceil(8));
+		for row in self.pixels.chunks(self.width.max(1)) {
+			for pixels in row.chunks(8) {
+				let byte = pixels
+					.iter()
+					.enumerate()
+					.filter(|&(_, &pixel)| pixel == dot)
+					.fold(0, |byte, (bit, _)| byte | 0x80 >> bit);
+				bytes.push(byte);
+			}
+		}
+		bytes
+	}
+
+	// binary PBM, where 1 is black
+	fn write_pbm(&self, file_name: &str) -> Result<()> {
+		let mut file = BufWriter::new(File::create(file_name)?);
+		write!(file, "P4\n{} {}\n", self.width, self.height)?;
+		file.write_all(&self.packed(true))?;
+		Ok(())
+	}
+
+	// 1-bit grayscale PNG, where 1 is white
+	fn write_png(&self, file_name: &str) -> Result<()> {
+		let file = BufWriter::new(File::create(file_name)?);
+		let mut encoder = png::Encoder::new(file, self.width as u32, self.height as u32);
+		encoder.set_color(png::ColorType::Grayscale);
+		encoder.set_depth(png::BitDepth::One);
+		encoder
+			.write_header()?
+			.write_image_data(&self.packed(false))?;
+		Ok(())
+	}
+
+	// two rows per line, which keeps the aspect ratio roughly square
+	fn half_blocks(&self) -> String {
+		(0..self.height)
+			.step_by(2)
+			.map(|y| {
+				(0..self.width)
+					.map(|x| match (self.get(x, y), self.get(x, y + 1)) {
+						(false, false) => ' ',
+						(true, false) => '▀',
+						(false, true) => '▄',
+						(true, true) => '█',
+					})
+					.collect::<String>()
+					.trim_end()
+					.to_owned()
+			})
+			.join("\n")
+	}
+}
+
+fn main() -> Result<()> {
+	color_eyre::install()?;
+	let mut print_rendering = false;
+	let mut print_unfoldings = false;
+	let mut font_file = None;
+	let mut input_file = None;
+	let mut export_file = None;
+	let mut print_half_blocks = false;
+	// fold step to export, 0 is the initial state, the last one by default
+	let mut export_step = None;
+	let mut args = std::env::args().skip(1);
+	while let Some(arg) = args.next() {
+		let mut value = || args.next().ok_or_else(|| eyre!("{} requires a value", arg));
+		match arg.as_str() {
+			"--render" => print_rendering = true,
+			"--font" => font_file = Some(value()?),
+			"--input" => input_file = Some(value()?),
+			"--unfold" => print_unfoldings = true,
+			"--export" => export_file = Some(value()?),
+			"--half-blocks" => print_half_blocks = true,
+			"--step" => export_step = Some(value()?.parse::<usize>()?),
+			_ => return Err(eyre!("unknown argument {}", arg)),
+		}
+	}
+	let mut font = Font::parse(LETTERS)?;
+	if let Some(file_name) = font_file {
+		font.extend(Font::parse(&read_to_string(file_name)?)?);
+	}
+
+	let (mut points, instructions) = read_input(input_file.as_deref().unwrap_or("day-13/input"))?;
+	points.sort_unstable();
+	points.dedup();
+	let export_step = export_step.unwrap_or(instructions.len());
+	if export_step > instructions.len() {
+		return Err(eyre!("there are only {} folds", instructions.len()));
+	}
+	let mut exported = (export_step == 0).then(|| points.clone());
+	for (step, instruction) in (1..).zip(instructions) {
+		let before = points.clone();
+		let shift = instruction.apply(&mut points);
+		println!("{}", points.len());
+		if step == export_step {
+			exported = Some(points.clone());
+		}
+
+		// going back the other way, which must include the actual points
+		if print_unfoldings {
+			let unfolding = instruction.unfold(&points, shift);
+			println!(
+				"unfolding {}: {} certain, {} ambiguous, {} possible sets{}",
+				instruction,
+				unfolding.certain.len(),
+				unfolding.either.len(),
+				unfolding
+					.count()
+					.map_or_else(|| "too many".to_owned(), |count| count.to_string()),
+				if unfolding.contains(&before) {
+					""
+				} else {
+					", but not the actual one!"
+				}
+			);
+			// small enough to check that every one of them folds back
+			if unfolding.count().is_some_and(|count| count <= 1000) {
+				let consistent = unfolding.sets().all(|mut set| {
+					instruction.apply(&mut set);
+					set == points
+				});
+				println!("all sets fold back: {}", consistent);
+			}
+		}
+	}
+	if print_rendering {
+		render(&mut points);
+	}
+	let exported = Bitmap::from_points(&exported.unwrap());
+	if print_half_blocks {
+		println!("{}", exported.half_blocks());
+	}
+	if let Some(file_name) = export_file {
+		if file_name.ends_with(".pbm") {
+			exported.write_pbm(&file_name)?;
+		} else if file_name.ends_with(".png") {
+			exported.write_png(&file_name)?;
+		} else {
+			return Err(eyre!("unsupported export format {}", file_name));
+		}
+	}
+	// no need to squint at the rendering anymore, unless it isn't text, which
+	// is still an error
+	match font.recognize(&points) {
+		Ok(text) => println!("{}", text),
+		Err(error) => {
+			if !print_rendering {
+				render(&mut points);
+			}
+			return Err(eyre!("not recognized as text, {}", error));
+		}
+	}
 	Ok(())
 }
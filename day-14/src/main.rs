@@ -1,11 +1,11 @@
-use color_eyre::Result;
+use color_eyre::{eyre::eyre, Result};
 use itertools::Itertools;
 use std::{
 	borrow::BorrowMut,
 	collections::HashMap,
 	fs::File,
 	io::{BufRead, BufReader},
-	mem::swap,
+	iter,
 };
 use thiserror::Error;
 
@@ -15,83 +15,553 @@ enum Error {
 	MissingTemplate,
 	#[error("incorrect rule format")]
 	IncorrectRuleFormat,
+	#[error("rules have windows of different lengths")]
+	MixedWindowLengths,
+	#[error("control characters are reserved for padding")]
+	ControlCharacter,
+	#[error("windows of length {1} don't determine what {0} turns into")]
+	AmbiguousWindow(String, usize),
+	#[error("counts exceed u128, use a modulus")]
+	Overflow,
 }
 
-use Error::{IncorrectRuleFormat, MissingTemplate};
+use Error::{
+	AmbiguousWindow, ControlCharacter, IncorrectRuleFormat, MissingTemplate, MixedWindowLengths,
+	Overflow,
+};
+
+// windows of k elements, k = 2 being the pairs of the puzzle
+type Window = Vec<char>;
 
-type Instructions = (String, Vec<((char, char), char)>);
+// rules map a window to a (possibly longer) insertion
+type Instructions = (String, Vec<(Window, Window)>);
 
 fn read_input(file_name: &str) -> Result<Instructions> {
 	let file = File::open(file_name)?;
 	let file = BufReader::new(file);
 	let mut lines = file.lines();
 	let template = lines.borrow_mut().next().ok_or(MissingTemplate)??;
+	if template.chars().any(char::is_control) {
+		return Err(ControlCharacter.into());
+	}
 	let rules: Vec<_> = lines
 		.filter_ok(|line| !line.is_empty())
 		.map(|line| -> Result<_> {
 			let line = line?;
-			let (pair, insertion) = line
+			let (window, insertion) = line
 				.split(" -> ")
 				.collect_tuple()
 				.ok_or(IncorrectRuleFormat)?;
-			let (a, b) = pair.chars().collect_tuple().ok_or(IncorrectRuleFormat)?;
-			let (c,) = insertion
-				.chars()
-				.collect_tuple()
-				.ok_or(IncorrectRuleFormat)?;
-			Ok(((a, b), c))
+			if window.chars().count() < 2 || insertion.is_empty() {
+				return Err(IncorrectRuleFormat.into());
+			}
+			if line.chars().any(char::is_control) {
+				return Err(ControlCharacter.into());
+			}
+			Ok((
+				window.chars().collect_vec(),
+				insertion.chars().collect_vec(),
+			))
 		})
 		.try_collect()?;
+	if !rules.iter().map(|(window, _)| window.len()).all_equal() {
+		return Err(MixedWindowLengths.into());
+	}
 	Ok((template, rules))
 }
 
-fn print_info(polymer: &HashMap<(char, char), usize>, first: char, last: char) {
-	let mut element_counts = HashMap::new();
-	for (&pair, &count) in polymer {
-		*element_counts.entry(pair.0).or_default() += count;
-		*element_counts.entry(pair.1).or_default() += count;
-	}
-	let element_counts = element_counts
-		.into_iter()
-		.sorted_by_key(|(element, count)| (*count, *element))
-		.collect_vec();
-	let rare = element_counts.first().unwrap();
-	let common = element_counts.last().unwrap();
-	let map_count =
-		|c: &(char, usize)| (c.1 + (c.0 == first) as usize + (c.0 == last) as usize) / 2;
-	let rare = map_count(rare);
-	let common = map_count(common);
-	println!("{}", common - rare);
+#[derive(Clone, Copy, Debug)]
+enum Arithmetic {
+	Checked,
+	// at most 2⁶⁴, so products still fit into u128
+	Modulo(u128),
+}
+
+impl Arithmetic {
+	fn add(self, a: u128, b: u128) -> Result<u128, Error> {
+		match self {
+			Arithmetic::Checked => a.checked_add(b).ok_or(Overflow),
+			Arithmetic::Modulo(m) => Ok((a + b) % m),
+		}
+	}
+
+	fn mul(self, a: u128, b: u128) -> Result<u128, Error> {
+		match self {
+			Arithmetic::Checked => a.checked_mul(b).ok_or(Overflow),
+			Arithmetic::Modulo(m) => Ok(a * b % m),
+		}
+	}
+}
+
+// square matrix with a list of nonzero entries per row
+#[derive(Clone, Debug)]
+struct SparseMatrix {
+	rows: Vec<Vec<(usize, u128)>>,
+}
+
+impl SparseMatrix {
+	fn identity(size: usize) -> Self {
+		SparseMatrix {
+			rows: (0..size).map(|i| vec![(i, 1)]).collect(),
+		}
+	}
+
+	fn mul(&self, other: &Self, arithmetic: Arithmetic) -> Result<Self, Error> {
+		let mut accumulator = vec![0; other.rows.len()];
+		let mut rows = Vec::with_capacity(self.rows.len());
+		for row in &self.rows {
+			accumulator.fill(0);
+			for &(k, a) in row {
+				for &(j, b) in &other.rows[k] {
+					accumulator[j] = arithmetic.add(accumulator[j], arithmetic.mul(a, b)?)?;
+				}
+			}
+			rows.push(
+				accumulator
+					.iter()
+					.copied()
+					.enumerate()
+					.filter(|&(_, value)| value != 0)
+					.collect(),
+			);
+		}
+		Ok(SparseMatrix { rows })
+	}
+
+	fn apply(&self, vector: &[u128], arithmetic: Arithmetic) -> Result<Vec<u128>, Error> {
+		self.rows
+			.iter()
+			.map(|row| {
+				row.iter().try_fold(0, |sum, &(j, a)| {
+					arithmetic.add(sum, arithmetic.mul(a, vector[j])?)
+				})
+			})
+			.collect()
+	}
+
+	// exponentiation by squaring, O(log n) matrix products
+	fn pow(&self, mut n: u64, arithmetic: Arithmetic) -> Result<Self, Error> {
+		let mut result = SparseMatrix::identity(self.rows.len());
+		let mut power = self.clone();
+		while n > 0 {
+			if n & 1 != 0 {
+				result = result.mul(&power, arithmetic)?;
+			}
+			n >>= 1;
+			if n > 0 {
+				power = power.mul(&power, arithmetic)?;
+			}
+		}
+		Ok(result)
+	}
+}
+
+// the polymer is padded with sentinels, which no rule matches: one at the
+// start, marking the first window, and width − 1 at the end, so that every
+// element starts exactly one window and the windows there know where it ends
+const START: char = '\u{2}';
+const END: char = '\u{3}';
+
+fn is_sentinel(element: char) -> bool {
+	element == START || element == END
+}
+
+// a window with visible sentinels
+fn show(window: &[char]) -> String {
+	window
+		.iter()
+		.map(|&element| match element {
+			START => '^',
+			END => '$',
+			element => element,
+		})
+		.collect()
+}
+
+// one step on the windows of the padded polymer: each element is followed by
+// the insertion of the rule window whose middle gap comes right after it, and
+// the windows of the next step starting at an element or its insertion are
+// produced by the window of this step starting middle − 1 elements earlier
+// (plus those before it for the very first window); with 2k − 2 elements, that
+// window covers every insertion its produced windows contain, as long as all
+// rule windows have a rule, and windows without one are resolved by looking
+// at the windows that can follow
+struct Stepper<'a> {
+	rules: &'a HashMap<Window, Window>,
+	// of the rule windows
+	length: usize,
+	// of the counted windows
+	width: usize,
+}
+
+impl Stepper<'_> {
+	// elements of the rule window before the gap that gets the insertion
+	fn before(&self) -> usize {
+		self.length / 2 - 1
+	}
+
+	// a stretch of the polymer after one step, with the positions its elements
+	// ended up at and the number of elements whose insertions are known, which
+	// stops at the first one that depends on elements after the stretch
+	fn step(&self, context: &[char]) -> (Vec<char>, Vec<usize>, usize) {
+		let before = self.before();
+		let mut stepped = Vec::new();
+		let mut positions = Vec::new();
+		for (i, &element) in context.iter().enumerate() {
+			positions.push(stepped.len());
+			stepped.push(element);
+			let end = i + self.length - before;
+			let known = &context[i.saturating_sub(before)..end.min(context.len())];
+			if known.iter().any(|&element| is_sentinel(element)) {
+				continue;
+			}
+			// only the first window needs these, and its start sentinel covers them
+			if i < before {
+				continue;
+			}
+			if end > context.len() {
+				return (stepped, positions, i);
+			}
+			if let Some(insertion) = self.rules.get(&context[i - before..end]) {
+				stepped.extend(insertion);
+			}
+		}
+		(stepped, positions, context.len())
+	}
+
+	// the windows of the next step produced by a window, given the elements
+	// that can follow each run of width − 1 elements
+	fn expand(
+		&self,
+		window: &[char],
+		successors: &HashMap<&[char], Vec<char>>,
+	) -> Result<Vec<Window>, Error> {
+		let padding = self.width - 1;
+		let ambiguous = || AmbiguousWindow(show(window), self.width);
+		// once the end is in sight, the rest of the padding follows
+		let pad = |mut context: Window| {
+			let trailing = context.iter().rev().take_while(|&&e| e == END).count();
+			if trailing > 0 {
+				context.extend(iter::repeat_n(END, padding - trailing));
+			}
+			context
+		};
+		// all ways the polymer can continue for as far as the insertions of the
+		// produced windows can depend on
+		let mut contexts = vec![pad(window.to_vec())];
+		for _ in 2..self.length {
+			contexts = contexts
+				.into_iter()
+				.flat_map(|context| {
+					if context.last() == Some(&END) {
+						return vec![context];
+					}
+					let run = &context[context.len() - padding..];
+					successors
+						.get(run)
+						.into_iter()
+						.flatten()
+						.map(|&next| pad(context.iter().copied().chain([next]).collect()))
+						.collect()
+				})
+				.collect();
+		}
+
+		let blocks = if window[0] == START {
+			0..=self.before()
+		} else {
+			self.before()..=self.before()
+		};
+		let mut expansion = None;
+		for context in contexts {
+			let (stepped, positions, known) = self.step(&context);
+			let mut produced = Vec::new();
+			for block in blocks.clone() {
+				if block >= known {
+					return Err(ambiguous());
+				}
+				let end = positions.get(block + 1).copied().unwrap_or(stepped.len());
+				for start in positions[block]..end {
+					match stepped.get(start..start + self.width) {
+						Some(window) => produced.push(window.to_vec()),
+						// past the end of the polymer
+						None if context.last() == Some(&END) => {}
+						None => return Err(ambiguous()),
+					}
+				}
+			}
+			match &expansion {
+				Some(expansion) if *expansion != produced => return Err(ambiguous()),
+				_ => expansion = Some(produced),
+			}
+		}
+		// no way to continue, so the window never actually occurs
+		Ok(expansion.unwrap_or_default())
+	}
+}
+
+// how much wider than the rule windows the counted windows may get
+const MAX_WIDTH_FACTOR: usize = 4;
+
+// one step maps window counts to window counts linearly, with each window of
+// the padded polymer turning into the windows it produces
+struct Transitions {
+	windows: Vec<Window>,
+	elements: Vec<char>,
+	// rule windows that can occur, but have no rule
+	unruled: Vec<Window>,
+	matrix: SparseMatrix,
+	// window counts of the template
+	initial: Vec<u128>,
+}
+
+impl Transitions {
+	// windows of 2k − 2 elements suffice if every rule window has a rule, and
+	// wider ones tell apart more of the places a window without one can be at
+	fn compile(template: &str, rules: &HashMap<Window, Window>) -> Result<Self> {
+		if template.is_empty() {
+			return Err(eyre!("empty template"));
+		}
+		let length = rules.keys().next().map_or(2, Vec::len);
+		let widths = 2 * length - 2..=MAX_WIDTH_FACTOR * length;
+		for width in widths.clone() {
+			match Self::compile_with_width(template, rules, length, width) {
+				Err(AmbiguousWindow(..)) if width < *widths.end() => continue,
+				transitions => return Ok(transitions?),
+			}
+		}
+		unreachable!()
+	}
+
+	fn compile_with_width(
+		template: &str,
+		rules: &HashMap<Window, Window>,
+		length: usize,
+		width: usize,
+	) -> Result<Self, Error> {
+		let stepper = Stepper {
+			rules,
+			length,
+			width,
+		};
+		let padded = iter::once(START)
+			.chain(template.chars())
+			.chain(iter::repeat_n(END, width - 1))
+			.collect_vec();
+		let counts = padded.windows(width).counts();
+
+		// all windows that can ever occur, starting from those of the template
+		// until expanding them yields nothing new
+		let mut windows = counts
+			.keys()
+			.map(|window| window.to_vec())
+			.sorted()
+			.collect_vec();
+		let expansions = loop {
+			let successors = windows
+				.iter()
+				.map(|window| (&window[..width - 1], window[width - 1]))
+				.into_group_map();
+			let expansions: Vec<_> = windows
+				.iter()
+				.map(|window| stepper.expand(window, &successors))
+				.try_collect()?;
+			let known = windows.len();
+			windows.extend(expansions.iter().flatten().cloned());
+			windows = windows.into_iter().sorted().dedup().collect();
+			if windows.len() == known {
+				break expansions;
+			}
+		};
+
+		let elements = windows
+			.iter()
+			.flatten()
+			.copied()
+			.filter(|&element| !is_sentinel(element))
+			.sorted()
+			.dedup()
+			.collect_vec();
+		let unruled = windows
+			.iter()
+			.flat_map(|window| window.windows(length))
+			.filter(|window| {
+				!window.iter().any(|&element| is_sentinel(element)) && !rules.contains_key(*window)
+			})
+			.map(|window| window.to_vec())
+			.sorted()
+			.dedup()
+			.collect();
+		let index = |window: &Window| windows.binary_search(window).unwrap();
+		let mut rows = vec![Vec::new(); windows.len()];
+		for (from, expansion) in expansions.iter().enumerate() {
+			for produced in expansion {
+				rows[index(produced)].push((from, 1));
+			}
+		}
+		for row in &mut rows {
+			// the same window may be produced more than once, e.g., AA -> A
+			*row = row
+				.iter()
+				.copied()
+				.into_grouping_map()
+				.sum()
+				.into_iter()
+				.sorted()
+				.collect();
+		}
+		let initial = windows
+			.iter()
+			.map(|window| counts.get(&window[..]).copied().unwrap_or(0) as u128)
+			.collect();
+		Ok(Transitions {
+			windows,
+			elements,
+			unruled,
+			matrix: SparseMatrix { rows },
+			initial,
+		})
+	}
+
+	// squaring fills in the matrix, so up to as many steps as there are windows,
+	// stepping is cheaper
+	fn window_counts_after(&self, steps: u64, arithmetic: Arithmetic) -> Result<Vec<u128>, Error> {
+		if steps > self.windows.len() as u64 {
+			return self
+				.matrix
+				.pow(steps, arithmetic)?
+				.apply(&self.initial, arithmetic);
+		}
+		let mut window_counts = self.initial.clone();
+		for _ in 0..steps {
+			window_counts = self.matrix.apply(&window_counts, arithmetic)?;
+		}
+		Ok(window_counts)
+	}
+
+	// every element is the first one of exactly one window, thanks to the
+	// padding
+	fn element_counts(
+		&self,
+		window_counts: &[u128],
+		arithmetic: Arithmetic,
+	) -> Result<Vec<(char, u128)>, Error> {
+		let mut counts = vec![0; self.elements.len()];
+		for (window, &count) in self.windows.iter().zip(window_counts) {
+			if let Ok(index) = self.elements.binary_search(&window[0]) {
+				counts[index] = arithmetic.add(counts[index], count)?;
+			}
+		}
+		Ok(self.elements.iter().copied().zip(counts).collect())
+	}
+
+	fn element_counts_after(
+		&self,
+		steps: u64,
+		arithmetic: Arithmetic,
+	) -> Result<Vec<(char, u128)>, Error> {
+		self.element_counts(&self.window_counts_after(steps, arithmetic)?, arithmetic)
+	}
+
+	// element counts after each of the first steps, including the template
+	fn history(&self, steps: u64) -> Result<Vec<Vec<(char, u128)>>, Error> {
+		let mut window_counts = self.initial.clone();
+		let mut history = vec![self.element_counts(&window_counts, Arithmetic::Checked)?];
+		for _ in 0..steps {
+			window_counts = self.matrix.apply(&window_counts, Arithmetic::Checked)?;
+			history.push(self.element_counts(&window_counts, Arithmetic::Checked)?);
+		}
+		Ok(history)
+	}
+}
+
+// most common minus least common element
+fn spread(element_counts: &[(char, u128)]) -> u128 {
+	let counts = element_counts.iter().map(|&(_, count)| count);
+	counts.clone().max().unwrap_or(0) - counts.min().unwrap_or(0)
 }
 
 fn main() -> Result<()> {
 	color_eyre::install()?;
-	let (template, rules) = read_input("day-14/input")?;
+	let mut steps = None;
+	let mut history_steps = None;
+	let mut arithmetic = Arithmetic::Checked;
+	let mut input_file = None;
+	let mut args = std::env::args().skip(1);
+	while let Some(arg) = args.next() {
+		let mut value = || args.next().ok_or_else(|| eyre!("{} requires a value", arg));
+		match arg.as_str() {
+			"--input" => input_file = Some(value()?),
+			"--steps" => steps = Some(value()?.parse::<u64>()?),
+			"--history" => history_steps = Some(value()?.parse::<u64>()?),
+			"--modulo" => {
+				let modulus = value()?.parse::<u64>()?;
+				if modulus == 0 {
+					return Err(eyre!("modulus must be positive"));
+				}
+				arithmetic = Arithmetic::Modulo(modulus as u128);
+			}
+			_ => return Err(eyre!("unknown argument {}", arg)),
+		}
+	}
+	let (template, rules) = read_input(input_file.as_deref().unwrap_or("day-14/input"))?;
+
+	let rules = HashMap::<Window, Window>::from_iter(rules);
+	let transitions = Transitions::compile(&template, &rules)?;
+	if !transitions.unruled.is_empty() {
+		eprintln!(
+			"note: no rules for windows {}, which insert nothing",
+			transitions
+				.unruled
+				.iter()
+				.map(|window| show(window))
+				.join(", ")
+		);
+	}
 
-	let rules = HashMap::<(char, char), char>::from_iter(rules);
+	for steps in [10, 40] {
+		let element_counts = transitions.element_counts_after(steps, Arithmetic::Checked)?;
+		println!("{}", spread(&element_counts));
+	}
 
-	let first = template.chars().next().unwrap();
-	let last = template.chars().next_back().unwrap();
+	// far more steps, exactly while counts fit into u128 or modulo some number
+	if let Some(steps) = steps {
+		let element_counts = transitions.element_counts_after(steps, arithmetic)?;
+		for (element, count) in &element_counts {
+			println!("{}: {}", element, count);
+		}
+		if let Arithmetic::Checked = arithmetic {
+			println!("spread: {}", spread(&element_counts));
+		}
+	}
 
-	let mut polymer = template.chars().tuple_windows().counts();
-	let mut new_polymer = HashMap::new();
-	for k in 0..40 {
-		if k == 10 {
-			print_info(&polymer, first, last);
+	// growth of every element, step by step
+	if let Some(steps) = history_steps {
+		let history = transitions.history(steps)?;
+		let length = |element_counts: &[(char, u128)]| -> u128 {
+			element_counts.iter().map(|&(_, count)| count).sum()
+		};
+		let width = history
+			.last()
+			.map_or(1, |counts| length(counts).to_string().len());
+		print!("step {:>width$}", "length", width = width.max(6));
+		for element in &transitions.elements {
+			print!(" {:>width$}", element, width = width);
 		}
-		new_polymer.clear();
-		for (&pair, &count) in &polymer {
-			if let Some(&insertion) = rules.get(&pair) {
-				*new_polymer.entry((pair.0, insertion)).or_default() += count;
-				*new_polymer.entry((insertion, pair.1)).or_default() += count;
-			} else {
-				*new_polymer.entry(pair).or_default() += count;
+		println!();
+		for (step, element_counts) in history.iter().enumerate() {
+			print!(
+				"{:>4} {:>width$}",
+				step,
+				length(element_counts),
+				width = width.max(6)
+			);
+			for (_, count) in element_counts {
+				print!(" {:>width$}", count, width = width);
 			}
+			println!();
 		}
-		swap(&mut polymer, &mut new_polymer);
 	}
 
-	print_info(&polymer, first, last);
-
 	Ok(())
 }
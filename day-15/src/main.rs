@@ -1,221 +1,155 @@
+use color_eyre::eyre::eyre;
 use color_eyre::Result;
-use common::read_digit_field;
-use ndarray::{concatenate, prelude::*, IntoDimension};
-use petgraph::algo::astar;
-
-#[derive(Clone, Debug)]
-struct GridGraph(Array2<u8>);
-
-#[derive(Clone, Debug, Default)]
-struct GridMap(Array2<bool>);
-
-impl petgraph::visit::GraphBase for GridGraph {
-	type EdgeId = ([usize; 2], [usize; 2]);
-	type NodeId = [usize; 2];
-}
-
-impl petgraph::visit::VisitMap<[usize; 2]> for GridMap {
-	fn visit(&mut self, a: [usize; 2]) -> bool {
-		let visited = self.is_visited(&a);
-		self.0[a] = true;
-		visited
-	}
-
-	fn is_visited(&self, a: &[usize; 2]) -> bool {
-		self.0[*a]
-	}
+use common::{read_digit_field, Grid, GridGraph, Node};
+use ndarray::prelude::*;
+use petgraph::{algo::astar, visit::EdgeRef};
+
+// the map repeated in both directions, with risk levels one higher for every
+// tile to the right or down, wrapping around from 9 to 1, and computed on the
+// fly instead of materializing the whole map
+struct TiledGrid {
+	tile: Array2<u8>,
+	tiles: usize,
 }
 
-impl petgraph::visit::Visitable for GridGraph {
-	type Map = GridMap;
+impl Grid for TiledGrid {
+	type Value = u8;
 
-	fn visit_map(&self) -> Self::Map {
-		GridMap::default()
+	fn dim(&self) -> (usize, usize) {
+		let (rows, columns) = self.tile.dim();
+		(rows * self.tiles, columns * self.tiles)
 	}
 
-	fn reset_map(&self, map: &mut Self::Map) {
-		map.0.fill(false);
-	}
-}
-
-#[derive(Clone, Debug)]
-struct GridGraphNeighbors {
-	node: [usize; 2],
-	shape: [usize; 2],
-	axis: u8,
-	hilo: i8,
-}
-
-impl GridGraphNeighbors {
-	fn new(node: [usize; 2], shape: Dim<[usize; 2]>) -> Self {
-		Self {
-			node,
-			shape: [shape[0], shape[1]],
-			axis: 0,
-			hilo: -1,
+	fn value(&self, node: Node) -> Option<u8> {
+		let (rows, columns) = self.tile.dim();
+		let (tile_r, r) = (node[0] / rows, node[0] % rows);
+		let (tile_c, c) = (node[1] / columns, node[1] % columns);
+		if tile_r >= self.tiles || tile_c >= self.tiles {
+			return None;
 		}
+		let risk = self.tile[[r, c]] as usize;
+		Some(((risk + tile_r + tile_c - 1) % 9 + 1) as u8)
 	}
 }
 
-impl Iterator for GridGraphNeighbors {
-	type Item = [usize; 2];
-
-	fn next(&mut self) -> Option<Self::Item> {
-		let Self {
-			node,
-			shape,
-			axis,
-			hilo,
-		} = self;
-
-		let mut result = None;
+fn lowest_total_risk<G, P, F>(graph: &GridGraph<G, P, F>) -> Option<usize>
+where
+	G: Grid,
+	P: Fn(G::Value) -> bool,
+	F: Fn(G::Value, G::Value) -> usize,
+{
+	let dim = graph.grid.dim();
+	let goal = [dim.0 - 1, dim.1 - 1];
+	astar(
+		graph,
+		[0, 0],
+		|node| node == goal,
+		|edge| *edge.weight(),
+		|node| (goal[0] - node[0]) + (goal[1] - node[1]),
+	)
+	.map(|(risk, _)| risk)
+}
 
-		while result.is_none() {
-			if *axis >= 2 {
-				return None;
+// costs are bytes, so distances in the queue span at most 256 values
+const BUCKETS: usize = u8::MAX as usize + 1;
+
+// Dijkstra's algorithm with dense distances, indexed by r * columns + c, and a
+// bucket queue, which keeps huge tilings in memory where A*'s hash maps don't
+fn lowest_total_risk_dense<G, P, F>(graph: &GridGraph<G, P, F>) -> Result<Option<u32>>
+where
+	G: Grid,
+	P: Fn(G::Value) -> bool,
+	F: Fn(G::Value, G::Value) -> u8,
+{
+	let (rows, columns) = graph.grid.dim();
+	// indices are stored as u32 to halve the queue
+	let cells = rows
+		.checked_mul(columns)
+		.filter(|&cells| cells <= u32::MAX as usize + 1)
+		.ok_or_else(|| eyre!("{}×{} cells are too many to index with u32", rows, columns))?;
+	let goal = cells - 1;
+	let mut distances = vec![u32::MAX; cells];
+	let mut buckets = vec![Vec::<u32>::new(); BUCKETS];
+	distances[0] = 0;
+	buckets[0].push(0);
+	let mut queued = 1;
+	let mut distance = 0u32;
+	while queued > 0 {
+		let bucket = distance as usize % BUCKETS;
+		while let Some(index) = buckets[bucket].pop() {
+			queued -= 1;
+			let index = index as usize;
+			// a shorter distance has been found since
+			if distances[index] != distance {
+				continue;
 			}
-			if node[*axis as usize] > 0 && *hilo == -1 {
-				let mut temp = *node;
-				temp[*axis as usize] -= 1;
-				result = Some(temp);
-			} else if node[*axis as usize] + 1 < shape[*axis as usize] && *hilo == 1 {
-				let mut temp = *node;
-				temp[*axis as usize] += 1;
-				result = Some(temp);
+			if index == goal {
+				return Ok(Some(distance));
 			}
-			if *hilo == -1 {
-				*hilo = 1;
-			} else {
-				*hilo = -1;
-				*axis += 1;
+			let node = [index / columns, index % columns];
+			let value = graph.grid.value(node).unwrap();
+			for &(dr, dc) in &graph.neighborhood {
+				let neighbor = match (
+					node[0].checked_add_signed(dr),
+					node[1].checked_add_signed(dc),
+				) {
+					(Some(r), Some(c)) => [r, c],
+					_ => continue,
+				};
+				// same as GridGraph::neighbor, without looking the value up twice
+				let next = graph
+					.grid
+					.value(neighbor)
+					.filter(|&next| (graph.passable)(next));
+				if let Some(next) = next {
+					let other = neighbor[0] * columns + neighbor[1];
+					let cost = (graph.cost)(value, next);
+					let new = distance
+						.checked_add(cost as u32)
+						.ok_or_else(|| eyre!("total risk exceeds u32"))?;
+					if new < distances[other] {
+						distances[other] = new;
+						buckets[new as usize % BUCKETS].push(other as u32);
+						queued += 1;
+					}
+				}
 			}
 		}
-
-		result
-	}
-}
-
-impl petgraph::visit::IntoNeighbors for &GridGraph {
-	type Neighbors = GridGraphNeighbors;
-
-	fn neighbors(self, a: Self::NodeId) -> Self::Neighbors {
-		GridGraphNeighbors::new(a, self.0.raw_dim())
-	}
-}
-
-impl petgraph::visit::Data for GridGraph {
-	type NodeWeight = ();
-	type EdgeWeight = ();
-}
-
-#[derive(Clone, Debug)]
-struct GridGraphNodeEdges(GridGraphNeighbors);
-
-impl GridGraphNodeEdges {
-	fn new(node: [usize; 2], shape: Dim<[usize; 2]>) -> Self {
-		Self(GridGraphNeighbors::new(node, shape))
-	}
-}
-
-impl Iterator for GridGraphNodeEdges {
-	type Item = ([usize; 2], [usize; 2], &'static ());
-
-	fn next(&mut self) -> Option<Self::Item> {
-		self.0.next().map(|other| (self.0.node, other, &()))
-	}
-}
-
-#[derive(Clone, Debug)]
-struct GridGraphEdges(GridGraphNodeEdges);
-
-impl GridGraphEdges {
-	fn new(shape: Dim<[usize; 2]>) -> Self {
-		Self(GridGraphNodeEdges::new([0, 0], shape))
-	}
-}
-
-impl Iterator for GridGraphEdges {
-	type Item = ([usize; 2], [usize; 2], &'static ());
-
-	fn next(&mut self) -> Option<Self::Item> {
-		if let Some(edge) = self.0.next() {
-			Some(edge)
-		} else {
-			let mut temp = self.0 .0.node;
-			let shape = self.0 .0.shape;
-			if temp[0] + 1 < shape[0] {
-				temp[0] += 1;
-				self.0 = GridGraphNodeEdges::new(temp, shape.into_dimension());
-				self.0.next()
-			} else if temp[1] + 1 < shape[1] {
-				temp[0] = 0;
-				temp[1] += 1;
-				self.0 = GridGraphNodeEdges::new(temp, shape.into_dimension());
-				self.0.next()
-			} else {
-				None
-			}
-		}
-	}
-}
-
-impl petgraph::visit::IntoEdgeReferences for &GridGraph {
-	type EdgeRef = ([usize; 2], [usize; 2], &'static ());
-	type EdgeReferences = GridGraphEdges;
-
-	fn edge_references(self) -> Self::EdgeReferences {
-		GridGraphEdges::new(self.0.raw_dim())
-	}
-}
-
-impl petgraph::visit::IntoEdges for &GridGraph {
-	type Edges = GridGraphNodeEdges;
-
-	fn edges(self, a: Self::NodeId) -> Self::Edges {
-		GridGraphNodeEdges::new(a, self.0.raw_dim())
+		distance += 1;
 	}
+	Ok(None)
 }
 
 fn main() -> Result<()> {
 	color_eyre::install()?;
-	let grid = GridGraph(read_digit_field("day-15/input")?);
-
-	let dim = grid.0.raw_dim();
-	let goal = [dim[0] - 1, dim[1] - 1];
-	if let Some(path) = astar(
-		&grid,
-		[0, 0],
-		|node| node == goal,
-		|edge| grid.0[edge.1] as usize,
-		|node| (goal[0] - node[0]) + (goal[1] - node[1]),
-	) {
-		println!("{:?}", path.0);
-	}
-
-	let tile = &grid.0;
-	let mut tiled_grid = grid.0.clone();
-	for k in 0..4 {
-		let wrapped = tile.map(|v| (v + k) % 9 + 1);
-		tiled_grid = concatenate![Axis(0), tiled_grid, wrapped];
+	let mut tiles = 5;
+	let mut args = std::env::args().skip(1);
+	while let Some(arg) = args.next() {
+		let mut value = || args.next().ok_or_else(|| eyre!("{} requires a value", arg));
+		match arg.as_str() {
+			"--tiles" => tiles = value()?.parse()?,
+			_ => return Err(eyre!("unknown argument {}", arg)),
+		}
 	}
-	let tile = tiled_grid.clone();
-	for k in 0..4 {
-		let wrapped = tile.map(|v| (v + k) % 9 + 1);
-		tiled_grid = concatenate![Axis(1), tiled_grid, wrapped];
+	if tiles == 0 {
+		return Err(eyre!("tiles must be positive"));
 	}
 
-	let tiled_grid = GridGraph(tiled_grid);
+	// entering a cell costs its risk level
+	let grid = GridGraph::new(read_digit_field("day-15/input")?, |_, risk| risk as usize);
+	if let Some(risk) = lowest_total_risk(&grid) {
+		println!("{:?}", risk);
+	}
 
-	let dim = tiled_grid.0.raw_dim();
-	let goal = [dim[0] - 1, dim[1] - 1];
-	if let Some(path) = astar(
-		&tiled_grid,
-		[0, 0],
-		|node| node == goal,
-		|edge| tiled_grid.0[edge.1] as usize,
-		|node| (goal[0] - node[0]) + (goal[1] - node[1]),
-	) {
-		println!("{:?}", path.0);
+	let tiled_grid = GridGraph::new(
+		TiledGrid {
+			tile: grid.grid,
+			tiles,
+		},
+		|_, risk| risk,
+	);
+	if let Some(risk) = lowest_total_risk_dense(&tiled_grid)? {
+		println!("{:?}", risk);
 	}
 
 	Ok(())